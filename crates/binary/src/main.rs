@@ -5,7 +5,10 @@ use engine::{
     hooks::{DropParams, InitParams, UpdateAndRenderParams},
     resources::{
         Resources,
+        level::{LevelLoader, LevelManager},
         sprite_map::{SpriteMapLoader, SpriteMapManager},
+        tile_kinds::{TileKindsLoader, TileKindsManager},
+        tiled::{TiledMapLoader, TiledMapManager},
     },
 };
 use libloading::{Library, Symbol};
@@ -77,6 +80,11 @@ impl<A: Allocator + Clone> Game<'_, A> {
 const WINDOW_WIDTH: u16 = 1920;
 const WINDOW_HEIGHT: u16 = 1080;
 
+/// Set to a file path to record all input into it
+const RECORD_ENV: &str = "GAME_RECORD";
+/// Set to a file path to replay the input recorded into it
+const REPLAY_ENV: &str = "GAME_REPLAY";
+
 pub fn main() -> Result<()> {
     // unsafe { backtrace_on_stack_overflow::enable() }
 
@@ -92,21 +100,27 @@ pub fn main() -> Result<()> {
     let window = video_subsystem
         .window("dev: game", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
         .position_centered()
+        .resizable()
         .build()?;
 
     let mut canvas = window.into_canvas();
     let event_pump = sdl_context.event_pump()?;
-    let mut events = Events::new(event_pump);
+    let gamepad_subsystem = sdl_context.gamepad()?;
+    let mut events = Events::new(event_pump, gamepad_subsystem, canvas.window().clone());
+    events.enable_text_input(video_subsystem.text_input(), canvas.window().clone());
+
+    if let Ok(path) = std::env::var(REPLAY_ENV) {
+        events.replay_from(path)?;
+    } else if let Ok(path) = std::env::var(RECORD_ENV) {
+        events.record_to(path)?;
+    }
 
     let mut camera = Camera::default();
 
+    let (pixel_w, pixel_h) = canvas.window().size_in_pixels();
     let tc = canvas.texture_creator();
-    let mut render_tex = tc.create_texture(
-        None,
-        sdl3::render::TextureAccess::Target,
-        WINDOW_WIDTH as u32,
-        WINDOW_HEIGHT as u32,
-    )?;
+    let mut render_tex =
+        tc.create_texture(None, sdl3::render::TextureAccess::Target, pixel_w, pixel_h)?;
 
     let tc2 = canvas.texture_creator();
 
@@ -114,7 +128,19 @@ pub fn main() -> Result<()> {
     let mut resources = Resources {
         sprites: SpriteMapManager::new(
             GlobalAllocator,
-            SpriteMapLoader::new(GlobalAllocator, tc2, resource_root),
+            SpriteMapLoader::new(GlobalAllocator, tc2, resource_root.clone()),
+        ),
+        tile_kinds: TileKindsManager::new(
+            GlobalAllocator,
+            TileKindsLoader::new(GlobalAllocator, resource_root.clone()),
+        ),
+        levels: LevelManager::new(
+            GlobalAllocator,
+            LevelLoader::new(GlobalAllocator, resource_root.clone()),
+        ),
+        tiled_maps: TiledMapManager::new(
+            GlobalAllocator,
+            TiledMapLoader::new(GlobalAllocator, resource_root),
         ),
     };
     let mut init_params = InitParams {
@@ -126,7 +152,6 @@ pub fn main() -> Result<()> {
     let game_memory = game.as_ref().unwrap().init(&mut init_params)?;
 
     let mut exit = false;
-    let mut prev_now_ms: u64 = 0;
 
     while !exit {
         let new_path = Game::<GlobalAllocator>::get_latest_library_path()?;
@@ -145,9 +170,18 @@ pub fn main() -> Result<()> {
 
         events.scan();
 
-        let now_ms = sdl3::timer::ticks();
-        let delta_ms = now_ms - prev_now_ms;
-        prev_now_ms = now_ms;
+        let now_ms = events.now_ms();
+        let delta_ms = events.delta_ms();
+
+        let window = *events.window();
+        if window.resized {
+            render_tex = tc.create_texture(
+                None,
+                sdl3::render::TextureAccess::Target,
+                window.w as u32,
+                window.h as u32,
+            )?;
+        }
 
         let mut res = Ok(false);
         canvas.with_texture_canvas(&mut render_tex, |tex_canvas| {
@@ -162,8 +196,8 @@ pub fn main() -> Result<()> {
                 resources: &mut resources,
                 now_ms,
                 delta_ms,
-                screen_w: WINDOW_WIDTH,
-                screen_h: WINDOW_HEIGHT,
+                screen_w: window.w,
+                screen_h: window.h,
                 memory: game_memory,
             };
             res = game.as_ref().unwrap().update_and_render(&mut params);
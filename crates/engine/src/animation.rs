@@ -42,6 +42,29 @@ impl<T: Clone> Animation<T> {
         assert!(!kf.is_empty(), "Empty keyframes");
         Self { keyframes: kf }
     }
+
+    /// Get the keyframes in order
+    pub fn keyframes(&self) -> &[Keyframe<T>] {
+        &self.keyframes
+    }
+
+    /// Get the value at a time when looping from 0, so that many things can share the
+    /// same clock instead of each having a cursor
+    pub fn value_at(&self, time_ms: u64) -> &T {
+        let total_ms = self
+            .keyframes
+            .last()
+            .map_or(0, |k| k.cumulative_duration_ms);
+        if total_ms == 0 {
+            return &self.keyframes[0].value;
+        }
+
+        let t = (time_ms % total_ms as u64) as u16;
+        let i = self
+            .keyframes
+            .partition_point(|k| k.cumulative_duration_ms <= t);
+        &self.keyframes[i].value
+    }
 }
 
 /// A playback cursor in an animation
@@ -100,3 +123,24 @@ impl AnimationCursor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_at_loops() {
+        let anim = Animation::new([
+            Keyframe::new(100, 'a'),
+            Keyframe::new(50, 'b'),
+            Keyframe::new(50, 'c'),
+        ]);
+
+        assert_eq!(*anim.value_at(0), 'a');
+        assert_eq!(*anim.value_at(99), 'a');
+        assert_eq!(*anim.value_at(100), 'b');
+        assert_eq!(*anim.value_at(199), 'c');
+        assert_eq!(*anim.value_at(200), 'a');
+        assert_eq!(*anim.value_at(10_160), 'c');
+    }
+}
@@ -4,6 +4,7 @@ use sdl3::render::FRect;
 pub struct ScreenSpace;
 pub struct WorldSpace;
 pub struct TileSpace;
+pub struct ChunkSpace;
 
 /// A point on the screen
 pub type ScreenPoint = Point2D<f64, ScreenSpace>;
@@ -36,19 +37,22 @@ pub type WorldBox = Box2D<f64, WorldSpace>;
 pub type WorldRect = Rect<f64, WorldSpace>;
 
 /// A point in the tilemap
-pub type TilePoint = Point2D<u16, TileSpace>;
+pub type TilePoint = Point2D<i32, TileSpace>;
 
 /// A size in the tilemap
-pub type TileSize = Size2D<u16, TileSpace>;
+pub type TileSize = Size2D<i32, TileSpace>;
 
 /// A vector in the tilemap
-pub type TileVector = Vector2D<u16, TileSpace>;
+pub type TileVector = Vector2D<i32, TileSpace>;
 
 /// A box in the tilemap
-pub type TileBox = Box2D<u16, TileSpace>;
+pub type TileBox = Box2D<i32, TileSpace>;
 
 /// A rect in the tilemap
-pub type TileRect = Rect<u16, TileSpace>;
+pub type TileRect = Rect<i32, TileSpace>;
+
+/// The position of a chunk in a chunked tilemap
+pub type ChunkPoint = Point2D<i32, ChunkSpace>;
 
 /// Conversion functions between coordinates in the different libraries
 pub mod convert {
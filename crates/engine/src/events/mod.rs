@@ -0,0 +1,816 @@
+//! Utilities to handle events
+
+use std::path::Path;
+
+use sdl3::{
+    EventPump, GamepadSubsystem,
+    event::Event,
+    gamepad::{Axis, Button, Gamepad},
+    keyboard::{Mod, Scancode, TextInputUtil},
+    mouse::MouseButton,
+    rect::Rect,
+    sys::{
+        gamepad::{SDL_GamepadAxis, SDL_GamepadButton},
+        scancode::SDL_Scancode,
+    },
+    video::Window,
+};
+
+use crate::coords::{ScreenBox, ScreenPoint, ScreenRect, ScreenVector};
+
+pub mod recording;
+use recording::{InputEvent, InputFrame, Player, Recorder, RecordingError};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyStatus {
+    pub down: bool,
+    pub since: u64,
+    pub mods: Mod,
+}
+
+impl Default for KeyStatus {
+    fn default() -> Self {
+        Self {
+            down: false,
+            since: 0,
+            mods: Mod::empty(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MouseBtnStatus {
+    pub down: bool,
+    pub pos: ScreenPoint,
+    pub since: u64,
+}
+
+/// A click-and-drag gesture with a mouse button held down
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Drag {
+    pub btn: MouseButton,
+    pub start: ScreenPoint,
+    pub current: ScreenPoint,
+    pub since: u64,
+}
+
+impl Drag {
+    /// How far the mouse moved since the drag started
+    pub fn delta(&self) -> ScreenVector {
+        self.current - self.start
+    }
+
+    /// The box between the start and current points, for use in box selection
+    pub fn screen_box(&self) -> ScreenBox {
+        ScreenBox::from_points([self.start, self.current])
+    }
+
+    /// Whether the mouse moved further than `threshold` pixels, which allows telling
+    /// drags apart from clicks
+    pub fn moved(&self, threshold: f64) -> bool {
+        self.delta().square_length() > threshold * threshold
+    }
+}
+
+/// The max number of gamepads that can be connected at the same time
+pub const MAX_GAMEPADS: usize = 4;
+
+/// A normalized analog stick position where each axis goes from -1 to 1
+pub type StickVector = euclid::default::Vector2D<f64>;
+
+/// One of the two analog sticks in a gamepad
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GamepadBtnStatus {
+    pub down: bool,
+    pub since: u64,
+}
+
+/// The analog values below which sticks and triggers are considered to be at rest,
+/// as a fraction of their full range
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeadZones {
+    pub stick: f64,
+    pub trigger: f64,
+}
+
+impl Default for DeadZones {
+    fn default() -> Self {
+        Self {
+            stick: 0.15,
+            trigger: 0.05,
+        }
+    }
+}
+
+impl DeadZones {
+    /// Remove the dead zone from a trigger value and rescale it back to `0..=1`
+    pub fn apply_trigger(&self, value: f64) -> f64 {
+        Self::rescale(value, self.trigger)
+    }
+
+    /// Remove the dead zone from a single stick axis and rescale it back to `-1..=1`
+    pub fn apply_stick_axis(&self, value: f64) -> f64 {
+        Self::rescale(value.abs(), self.stick).copysign(value)
+    }
+
+    /// Remove the radial dead zone from a stick and rescale it so its length is
+    /// back to `0..=1`
+    pub fn apply_stick(&self, value: StickVector) -> StickVector {
+        let length = value.length();
+        if length == 0.0 {
+            return StickVector::zero();
+        }
+        value * (Self::rescale(length, self.stick) / length)
+    }
+
+    fn rescale(value: f64, dead_zone: f64) -> f64 {
+        if value <= dead_zone {
+            return 0.0;
+        }
+        ((value - dead_zone) / (1.0 - dead_zone)).min(1.0)
+    }
+}
+
+/// An open gamepad and the state of its inputs
+struct GamepadStatus {
+    /// Not opened when replaying a recording
+    pad: Option<Gamepad>,
+    id: u32,
+    btns: [GamepadBtnStatus; SDL_GamepadButton::COUNT.0 as usize],
+    axes: [i16; SDL_GamepadAxis::COUNT.0 as usize],
+}
+
+impl GamepadStatus {
+    fn new(pad: Option<Gamepad>, id: u32) -> Self {
+        Self {
+            pad,
+            id,
+            btns: [GamepadBtnStatus::default(); SDL_GamepadButton::COUNT.0 as usize],
+            axes: [0; SDL_GamepadAxis::COUNT.0 as usize],
+        }
+    }
+
+    /// Get the raw value of an axis normalized to `-1..=1`
+    fn axis(&self, axis: Axis) -> f64 {
+        // i16::MIN is one step further than i16::MAX
+        (self.axes[axis as usize] as f64 / i16::MAX as f64).max(-1.0)
+    }
+}
+
+/// Find the slot of a connected gamepad by its joystick ID
+fn gamepad_slot_mut(
+    gamepads: &mut [Option<GamepadStatus>],
+    id: u32,
+) -> Option<&mut Option<GamepadStatus>> {
+    gamepads
+        .iter_mut()
+        .find(|g| g.as_ref().is_some_and(|g| g.id == id))
+}
+
+/// Returned when querying a gamepad that is not connected
+const GAMEPAD_BTN_RELEASED: GamepadBtnStatus = GamepadBtnStatus {
+    down: false,
+    since: 0,
+};
+
+/// Text that is still being composed by an IME and was not committed yet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextComposition {
+    pub text: String,
+    /// Where the cursor is in `text`, in characters
+    pub cursor: i32,
+    /// How many characters after the cursor are selected
+    pub selection_len: i32,
+}
+
+/// The text input state of a window
+struct TextInput {
+    util: TextInputUtil,
+    window: Window,
+    active: bool,
+    text: String,
+    composition: TextComposition,
+}
+
+/// The state of the game window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WindowStatus {
+    /// The size in pixels, which is what render targets should be sized to. Every mouse
+    /// position is in pixels too: SDL gives them in window coordinates, which differ
+    /// from pixels on high-DPI displays, so they're scaled when scanned.
+    pub w: u16,
+    pub h: u16,
+    pub focused: bool,
+    pub minimized: bool,
+    /// Whether the window was resized during the last scan
+    pub resized: bool,
+    /// Whether the window gained or lost focus during the last scan
+    pub focus_changed: bool,
+}
+
+/// The input state that the events of each frame are applied to
+struct InputState {
+    /// How much the mouse moved since the last scan
+    mouse_delta: ScreenVector,
+    /// How much the mouse wheel scrolled since the last scan. Positive `y` is away from
+    /// the user and positive `x` is to the right.
+    wheel: ScreenVector,
+    quit: bool,
+    window: WindowStatus,
+    mouse_btns: [MouseBtnStatus; 8],
+    keys: [KeyStatus; SDL_Scancode::COUNT.0 as usize],
+    drag: Option<Drag>,
+    drag_released: Option<Drag>,
+    /// Gamepads stay in the same slot while connected, so the slot index can be
+    /// used as the player index
+    gamepads: [Option<GamepadStatus>; MAX_GAMEPADS],
+    text_input: Option<TextInput>,
+}
+
+impl InputState {
+    fn new(window_w: u16, window_h: u16) -> Self {
+        Self {
+            mouse_delta: ScreenVector::zero(),
+            wheel: ScreenVector::zero(),
+            quit: false,
+            window: WindowStatus {
+                w: window_w,
+                h: window_h,
+                focused: true,
+                minimized: false,
+                resized: false,
+                focus_changed: false,
+            },
+            mouse_btns: [MouseBtnStatus::default(); 8],
+            keys: [KeyStatus::default(); SDL_Scancode::COUNT.0 as usize],
+            drag: None,
+            drag_released: None,
+            gamepads: Default::default(),
+            text_input: None,
+        }
+    }
+
+    /// The player's gamepad, or `None` if it isn't connected or the player index is
+    /// out of range
+    fn gamepad(&self, player: usize) -> Option<&GamepadStatus> {
+        self.gamepads.get(player).and_then(Option::as_ref)
+    }
+
+    /// Reset the state that only lasts for one scan
+    fn start_frame(&mut self) {
+        self.mouse_delta = ScreenVector::zero();
+        self.wheel = ScreenVector::zero();
+        self.drag_released = None;
+        self.window.resized = false;
+        self.window.focus_changed = false;
+        if let Some(text_input) = &mut self.text_input {
+            text_input.text.clear();
+        }
+    }
+
+    /// Apply every event of a frame, which all happened at the frame's time.
+    ///
+    /// `open_gamepad` gives the status of a newly connected gamepad, or `None` if it
+    /// couldn't be opened.
+    fn apply_frame<F>(&mut self, frame: &InputFrame, mut open_gamepad: F)
+    where
+        F: FnMut(u32) -> Option<GamepadStatus>,
+    {
+        for event in &frame.events {
+            self.apply(event, frame.now_ms, &mut open_gamepad);
+        }
+    }
+
+    /// Update the input state with a single event
+    fn apply<F>(&mut self, event: &InputEvent, now: u64, open_gamepad: &mut F)
+    where
+        F: FnMut(u32) -> Option<GamepadStatus>,
+    {
+        match *event {
+            InputEvent::Quit => {
+                self.quit = true;
+            }
+            InputEvent::WindowResized { w, h } => {
+                self.window.w = w.clamp(1, u16::MAX as i32) as u16;
+                self.window.h = h.clamp(1, u16::MAX as i32) as u16;
+                self.window.resized = true;
+            }
+            InputEvent::WindowFocus { focused } => {
+                self.window.focus_changed |= self.window.focused != focused;
+                self.window.focused = focused;
+            }
+            InputEvent::WindowMinimized { minimized } => {
+                self.window.minimized = minimized;
+            }
+            InputEvent::MouseMotion { x, y, xrel, yrel } => {
+                self.mouse_delta += ScreenVector::new(xrel.into(), yrel.into());
+
+                if let Some(drag) = &mut self.drag {
+                    drag.current = ScreenPoint::new(x.into(), y.into());
+                }
+            }
+            InputEvent::MouseWheel { x, y } => {
+                self.wheel += ScreenVector::new(x.into(), y.into());
+            }
+            InputEvent::MouseButton { btn, down, x, y } => {
+                let mouse_btn = MouseButton::from_ll(btn);
+                let pos = ScreenPoint::new(x.into(), y.into());
+
+                let idx = mouse_btn as usize;
+                self.mouse_btns[idx].down = down;
+                self.mouse_btns[idx].since = now;
+                self.mouse_btns[idx].pos = pos;
+
+                if !down {
+                    if let Some(mut drag) = self.drag.take_if(|d| d.btn == mouse_btn) {
+                        drag.current = pos;
+                        self.drag_released = Some(drag);
+                    }
+                } else if self.drag.is_none() {
+                    // only the first pressed button starts a drag
+                    self.drag = Some(Drag {
+                        btn: mouse_btn,
+                        start: pos,
+                        current: pos,
+                        since: now,
+                    });
+                }
+            }
+            InputEvent::Key {
+                scancode,
+                down,
+                mods,
+            } => {
+                let Some(key) = Scancode::from_i32(scancode) else {
+                    return;
+                };
+
+                let idx = key as usize;
+                self.keys[idx].down = down;
+                self.keys[idx].since = now;
+                self.keys[idx].mods = Mod::from_bits_truncate(mods);
+            }
+            InputEvent::TextInput { ref text } => {
+                let Some(text_input) = self.text_input.as_mut().filter(|t| t.active) else {
+                    return;
+                };
+
+                text_input.text.push_str(text);
+                text_input.composition = TextComposition::default();
+            }
+            InputEvent::TextEditing {
+                ref text,
+                start,
+                length,
+            } => {
+                let Some(text_input) = self.text_input.as_mut().filter(|t| t.active) else {
+                    return;
+                };
+
+                text_input.composition.text.clone_from(text);
+                text_input.composition.cursor = start;
+                text_input.composition.selection_len = length;
+            }
+            InputEvent::GamepadAdded { id } => {
+                let Some(slot) = self.gamepads.iter_mut().find(|g| g.is_none()) else {
+                    sdl3::log::log_warn(sdl3::log::Category::Input, "too many gamepads");
+                    return;
+                };
+
+                if let Some(gamepad) = open_gamepad(id) {
+                    *slot = Some(gamepad);
+                }
+            }
+            InputEvent::GamepadRemoved { id } => {
+                // dropping the gamepad closes it
+                if let Some(slot) = gamepad_slot_mut(&mut self.gamepads, id) {
+                    *slot = None;
+                }
+            }
+            InputEvent::GamepadButton { id, btn, down } => {
+                let Some(Some(gamepad)) = gamepad_slot_mut(&mut self.gamepads, id) else {
+                    return;
+                };
+                let Some(button) = Button::from_ll(SDL_GamepadButton(btn)) else {
+                    return;
+                };
+
+                let idx = button as usize;
+                gamepad.btns[idx].down = down;
+                gamepad.btns[idx].since = now;
+            }
+            InputEvent::GamepadAxis { id, axis, value } => {
+                let Some(Some(gamepad)) = gamepad_slot_mut(&mut self.gamepads, id) else {
+                    return;
+                };
+                let Some(axis) = Axis::from_ll(SDL_GamepadAxis(axis)) else {
+                    return;
+                };
+
+                gamepad.axes[axis as usize] = value;
+            }
+        }
+    }
+}
+
+pub struct Events {
+    pump: EventPump,
+    gamepad_subsystem: GamepadSubsystem,
+    /// Tells how many pixels there are per window coordinate
+    window: Window,
+    pub dead_zones: DeadZones,
+    pub mouse_pos: ScreenPoint,
+    /// How much the mouse moved since the last scan
+    pub mouse_delta: ScreenVector,
+    /// How much the mouse wheel scrolled since the last scan. Positive `y` is away from
+    /// the user and positive `x` is to the right.
+    pub wheel: ScreenVector,
+    input: InputState,
+    /// The frame that was last scanned
+    frame: InputFrame,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+}
+
+impl Events {
+    pub fn new(pump: EventPump, gamepad_subsystem: GamepadSubsystem, window: Window) -> Events {
+        let (window_w, window_h) = window.size_in_pixels();
+        Events {
+            pump,
+            gamepad_subsystem,
+            window,
+            dead_zones: DeadZones::default(),
+            mouse_pos: ScreenPoint::default(),
+            mouse_delta: ScreenVector::zero(),
+            wheel: ScreenVector::zero(),
+            input: InputState::new(window_w as u16, window_h as u16),
+            frame: InputFrame::new(),
+            recorder: None,
+            player: None,
+        }
+    }
+
+    /// Allow the game to receive text input in a window. Text input still has to be
+    /// started with `start_text_input`.
+    pub fn enable_text_input(&mut self, util: TextInputUtil, window: Window) {
+        self.input.text_input = Some(TextInput {
+            util,
+            window,
+            active: false,
+            text: String::new(),
+            composition: TextComposition::default(),
+        });
+    }
+
+    /// Start writing every scanned frame to a file
+    pub fn record_to(&mut self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    /// Read frames from a recording instead of the event pump until it runs out, at
+    /// which point a quit is requested.
+    ///
+    /// Recorded resizes are replayed without resizing the real window, so replays assume
+    /// the window has the size and pixel density it had when recording.
+    pub fn replay_from(&mut self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        self.player = Some(Player::open(path)?);
+        Ok(())
+    }
+
+    /// Rescan the event pump (or the recording being replayed) for the newest events
+    pub fn scan(&mut self) {
+        self.input.start_frame();
+
+        match &mut self.player {
+            Some(player) => {
+                // still allow closing the window during playback
+                for event in self.pump.poll_iter() {
+                    if let Event::Quit { .. } = event {
+                        self.input.quit = true;
+                    }
+                }
+
+                match player.next_frame() {
+                    Some(Ok(frame)) => self.frame = frame,
+                    Some(Err(err)) => {
+                        sdl3::log::log_warn(
+                            sdl3::log::Category::Input,
+                            &format!("stopping playback: {err}"),
+                        );
+                        self.player = None;
+                        self.input.quit = true;
+                        self.frame.events.clear();
+                    }
+                    // the last frame's events were already applied once
+                    None => {
+                        self.player = None;
+                        self.input.quit = true;
+                        self.frame.events.clear();
+                    }
+                }
+            }
+            None => {
+                let now = sdl3::timer::ticks();
+                self.frame.delta_ms = now - self.frame.now_ms;
+                self.frame.now_ms = now;
+
+                let pixel_density = self.window.pixel_density();
+                let mouse_state = self.pump.mouse_state();
+                self.frame.mouse_pos = ScreenPoint::new(
+                    (mouse_state.x() * pixel_density).into(),
+                    (mouse_state.y() * pixel_density).into(),
+                );
+
+                self.frame.events.clear();
+                self.frame.events.extend(
+                    self.pump
+                        .poll_iter()
+                        .filter_map(|event| InputEvent::from_sdl(event, pixel_density)),
+                );
+
+                if let Some(recorder) = &mut self.recorder
+                    && let Err(err) = recorder.write_frame(&self.frame)
+                {
+                    sdl3::log::log_warn(
+                        sdl3::log::Category::Input,
+                        &format!("stopping recording: {err}"),
+                    );
+                    self.recorder = None;
+                }
+            }
+        }
+
+        self.mouse_pos = self.frame.mouse_pos;
+
+        let replaying = self.player.is_some();
+        let gamepad_subsystem = &self.gamepad_subsystem;
+        self.input.apply_frame(&self.frame, |id| {
+            // there is no device to open when replaying
+            if replaying {
+                return Some(GamepadStatus::new(None, id));
+            }
+
+            match gamepad_subsystem.open(id) {
+                Ok(pad) => Some(GamepadStatus::new(Some(pad), id)),
+                Err(err) => {
+                    sdl3::log::log_warn(
+                        sdl3::log::Category::Input,
+                        &format!("failed to open gamepad: {err}"),
+                    );
+                    None
+                }
+            }
+        });
+        self.mouse_delta = self.input.mouse_delta;
+        self.wheel = self.input.wheel;
+    }
+
+    /// The time at the last scan
+    pub fn now_ms(&self) -> u64 {
+        self.frame.now_ms
+    }
+
+    /// The time between the last two scans
+    pub fn delta_ms(&self) -> u64 {
+        self.frame.delta_ms
+    }
+
+    pub fn mouse_btn(&self, btn: MouseButton) -> &MouseBtnStatus {
+        &self.input.mouse_btns[btn as usize]
+    }
+
+    /// The drag gesture in progress, if any
+    pub fn drag(&self) -> Option<&Drag> {
+        self.input.drag.as_ref()
+    }
+
+    /// The drag gesture that was released during the last scan, if any
+    pub fn drag_released(&self) -> Option<&Drag> {
+        self.input.drag_released.as_ref()
+    }
+
+    pub fn key(&self, key: Scancode) -> &KeyStatus {
+        &self.input.keys[key as usize]
+    }
+
+    /// Whether there is a gamepad connected for the player
+    pub fn gamepad_connected(&self, player: usize) -> bool {
+        self.input.gamepad(player).is_some()
+    }
+
+    /// The name of the player's gamepad if it's connected
+    pub fn gamepad_name(&self, player: usize) -> Option<String> {
+        self.input
+            .gamepad(player)
+            .and_then(|g| g.pad.as_ref())
+            .and_then(|p| p.name())
+    }
+
+    /// Get a player's gamepad button. Buttons of disconnected gamepads are always released.
+    pub fn gamepad_btn(&self, player: usize, btn: Button) -> &GamepadBtnStatus {
+        match self.input.gamepad(player) {
+            Some(gamepad) => &gamepad.btns[btn as usize],
+            None => &GAMEPAD_BTN_RELEASED,
+        }
+    }
+
+    /// Get a player's gamepad axis normalized to `-1..=1` for sticks and `0..=1` for
+    /// triggers with the dead zones applied.
+    ///
+    /// Stick axes are filtered individually, use `gamepad_stick` for a radial dead zone.
+    pub fn gamepad_axis(&self, player: usize, axis: Axis) -> f64 {
+        let Some(gamepad) = self.input.gamepad(player) else {
+            return 0.0;
+        };
+
+        let value = gamepad.axis(axis);
+        match axis {
+            Axis::TriggerLeft | Axis::TriggerRight => self.dead_zones.apply_trigger(value),
+            _ => self.dead_zones.apply_stick_axis(value),
+        }
+    }
+
+    /// Get a player's analog stick with the radial dead zone applied. Positive `y` is down.
+    pub fn gamepad_stick(&self, player: usize, stick: Stick) -> StickVector {
+        let Some(gamepad) = self.input.gamepad(player) else {
+            return StickVector::zero();
+        };
+
+        let (x, y) = match stick {
+            Stick::Left => (Axis::LeftX, Axis::LeftY),
+            Stick::Right => (Axis::RightX, Axis::RightY),
+        };
+        self.dead_zones
+            .apply_stick(StickVector::new(gamepad.axis(x), gamepad.axis(y)))
+    }
+
+    /// Start receiving text input and show the on-screen keyboard or IME if needed.
+    ///
+    /// Panics if text input was not enabled.
+    pub fn start_text_input(&mut self) {
+        let text_input = self
+            .input
+            .text_input
+            .as_mut()
+            .expect("Text input was not enabled");
+
+        if !text_input.active {
+            text_input.util.start(&text_input.window);
+            text_input.active = true;
+        }
+    }
+
+    /// Stop receiving text input, dropping any text that was being composed
+    pub fn stop_text_input(&mut self) {
+        let Some(text_input) = &mut self.input.text_input else {
+            return;
+        };
+
+        if text_input.active {
+            text_input.util.stop(&text_input.window);
+            text_input.active = false;
+            text_input.composition = TextComposition::default();
+        }
+    }
+
+    /// Whether text input was started
+    pub fn text_input_active(&self) -> bool {
+        self.input.text_input.as_ref().is_some_and(|t| t.active)
+    }
+
+    /// Tell the IME where the text field is on the screen so it can place its
+    /// candidate list next to it. `cursor` is the cursor's offset from the left of the field.
+    pub fn set_text_input_area(&self, area: &ScreenRect, cursor: i32) {
+        let Some(text_input) = &self.input.text_input else {
+            return;
+        };
+
+        // SDL places it in window coordinates
+        let scale = 1.0 / self.window.pixel_density() as f64;
+        let area = area.scale(scale, scale);
+        let rect = Rect::new(
+            area.origin.x as i32,
+            area.origin.y as i32,
+            area.size.width as u32,
+            area.size.height as u32,
+        );
+        text_input.util.set_rect(&text_input.window, rect, cursor);
+    }
+
+    /// The text that was committed since the last scan
+    pub fn text(&self) -> &str {
+        self.input.text_input.as_ref().map_or("", |t| &t.text)
+    }
+
+    /// The text being composed by the IME, if any
+    pub fn text_composition(&self) -> Option<&TextComposition> {
+        self.input
+            .text_input
+            .as_ref()
+            .map(|t| &t.composition)
+            .filter(|c| !c.text.is_empty())
+    }
+
+    pub fn window(&self) -> &WindowStatus {
+        &self.input.window
+    }
+
+    pub fn quit(&self) -> bool {
+        self.input.quit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_zones_stick() {
+        let dz = DeadZones {
+            stick: 0.2,
+            trigger: 0.0,
+        };
+
+        assert_eq!(
+            dz.apply_stick(StickVector::new(0.1, 0.1)),
+            StickVector::zero()
+        );
+        assert_eq!(
+            dz.apply_stick(StickVector::new(1.0, 0.0)),
+            StickVector::new(1.0, 0.0)
+        );
+
+        let half = dz.apply_stick(StickVector::new(0.0, -0.6));
+        assert!((half.y + 0.5).abs() < 1e-9);
+        assert_eq!(half.x, 0.0);
+
+        assert_eq!(dz.apply_stick_axis(-0.1), 0.0);
+        assert!((dz.apply_stick_axis(-0.6) + 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_since_is_frame_time() {
+        let mut input = InputState::new(800, 600);
+        let mut frame = InputFrame::new();
+        frame.now_ms = 1234;
+        frame.events.push(InputEvent::Key {
+            scancode: Scancode::F5 as i32,
+            down: true,
+            mods: 0,
+        });
+        frame.events.push(InputEvent::MouseButton {
+            btn: MouseButton::Right as u8,
+            down: true,
+            x: 10.0,
+            y: 20.0,
+        });
+        frame.events.push(InputEvent::GamepadAdded { id: 7 });
+        frame.events.push(InputEvent::GamepadButton {
+            id: 7,
+            btn: Button::South as i32,
+            down: true,
+        });
+
+        input.apply_frame(&frame, |id| Some(GamepadStatus::new(None, id)));
+
+        assert_eq!(input.keys[Scancode::F5 as usize].since, 1234);
+        let right = input.mouse_btns[MouseButton::Right as usize];
+        assert!(right.down);
+        assert_eq!(right.since, 1234);
+        assert_eq!(input.drag.unwrap().since, 1234);
+        let gamepad = input.gamepads[0].as_ref().unwrap();
+        assert_eq!(gamepad.btns[Button::South as usize].since, 1234);
+    }
+
+    #[test]
+    fn test_gamepad_out_of_range() {
+        let mut input = InputState::new(800, 600);
+        let mut frame = InputFrame::new();
+        frame.events.push(InputEvent::GamepadAdded { id: 7 });
+        input.apply_frame(&frame, |id| Some(GamepadStatus::new(None, id)));
+
+        assert!(input.gamepad(0).is_some());
+        assert!(input.gamepad(1).is_none());
+        assert!(input.gamepad(MAX_GAMEPADS).is_none());
+        assert!(input.gamepad(usize::MAX).is_none());
+    }
+
+    #[test]
+    fn test_dead_zones_trigger() {
+        let dz = DeadZones {
+            stick: 0.0,
+            trigger: 0.5,
+        };
+
+        assert_eq!(dz.apply_trigger(0.25), 0.0);
+        assert_eq!(dz.apply_trigger(0.75), 0.5);
+        assert_eq!(dz.apply_trigger(1.0), 1.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Recording and playback of input so sessions can be reproduced exactly
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    path::Path,
+};
+
+use allocator_api2::{alloc::Global as GlobalAllocator, vec::Vec};
+use sdl3::{
+    event::{Event, WindowEvent},
+    mouse::MouseWheelDirection,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::coords::ScreenPoint;
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("Failed to read or write recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid recording frame: {0}")]
+    InvalidFrame(#[from] serde_json::Error),
+}
+
+/// An input event as seen by `Events`.
+///
+/// SDL types are stored as their raw values so they can be serialized.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    Quit,
+    /// The window's size in pixels changed, which on high-DPI displays is larger than
+    /// its size in window coordinates
+    WindowResized {
+        w: i32,
+        h: i32,
+    },
+    WindowFocus {
+        focused: bool,
+    },
+    WindowMinimized {
+        minimized: bool,
+    },
+    /// Positions are in pixels, like the window's size
+    MouseMotion {
+        x: f32,
+        y: f32,
+        xrel: f32,
+        yrel: f32,
+    },
+    /// Already corrected for flipped wheels
+    MouseWheel {
+        x: f32,
+        y: f32,
+    },
+    /// The position is in pixels, like the window's size
+    MouseButton {
+        btn: u8,
+        down: bool,
+        x: f32,
+        y: f32,
+    },
+    Key {
+        scancode: i32,
+        down: bool,
+        mods: u16,
+    },
+    TextInput {
+        text: String,
+    },
+    TextEditing {
+        text: String,
+        start: i32,
+        length: i32,
+    },
+    GamepadAdded {
+        id: u32,
+    },
+    GamepadRemoved {
+        id: u32,
+    },
+    GamepadButton {
+        id: u32,
+        btn: i32,
+        down: bool,
+    },
+    GamepadAxis {
+        id: u32,
+        axis: i32,
+        value: i16,
+    },
+}
+
+impl InputEvent {
+    /// Convert an SDL event, returning `None` for events we don't handle. Mouse
+    /// positions are scaled from window coordinates to pixels by `pixel_density`
+    pub fn from_sdl(event: Event, pixel_density: f32) -> Option<Self> {
+        let input = match event {
+            Event::Quit { .. } => Self::Quit,
+            Event::Window { win_event, .. } => match win_event {
+                WindowEvent::PixelSizeChanged(w, h) => Self::WindowResized { w, h },
+                WindowEvent::FocusGained => Self::WindowFocus { focused: true },
+                WindowEvent::FocusLost => Self::WindowFocus { focused: false },
+                WindowEvent::Minimized => Self::WindowMinimized { minimized: true },
+                WindowEvent::Restored | WindowEvent::Maximized => {
+                    Self::WindowMinimized { minimized: false }
+                }
+                _ => return None,
+            },
+            Event::MouseMotion {
+                x, y, xrel, yrel, ..
+            } => Self::MouseMotion {
+                x: x * pixel_density,
+                y: y * pixel_density,
+                xrel: xrel * pixel_density,
+                yrel: yrel * pixel_density,
+            },
+            Event::MouseWheel {
+                x, y, direction, ..
+            } => {
+                let sign = match direction {
+                    MouseWheelDirection::Flipped => -1.0,
+                    _ => 1.0,
+                };
+                Self::MouseWheel {
+                    x: x * sign,
+                    y: y * sign,
+                }
+            }
+            Event::MouseButtonUp {
+                x, y, mouse_btn, ..
+            } => Self::MouseButton {
+                btn: mouse_btn as u8,
+                down: false,
+                x: x * pixel_density,
+                y: y * pixel_density,
+            },
+            Event::MouseButtonDown {
+                x, y, mouse_btn, ..
+            } => Self::MouseButton {
+                btn: mouse_btn as u8,
+                down: true,
+                x: x * pixel_density,
+                y: y * pixel_density,
+            },
+            Event::KeyUp {
+                scancode, keymod, ..
+            }
+            | Event::KeyDown {
+                scancode, keymod, ..
+            } => {
+                let Some(key) = scancode else {
+                    sdl3::log::log_warn(sdl3::log::Category::Input, "received unknown key");
+                    return None;
+                };
+
+                Self::Key {
+                    scancode: key.to_i32(),
+                    down: matches!(event, Event::KeyDown { .. }),
+                    mods: keymod.bits(),
+                }
+            }
+            Event::TextInput { text, .. } => Self::TextInput { text },
+            Event::TextEditing {
+                text,
+                start,
+                length,
+                ..
+            } => Self::TextEditing {
+                text,
+                start,
+                length,
+            },
+            Event::ControllerDeviceAdded { which, .. } => Self::GamepadAdded { id: which },
+            Event::ControllerDeviceRemoved { which, .. } => Self::GamepadRemoved { id: which },
+            Event::ControllerButtonUp { which, button, .. } => Self::GamepadButton {
+                id: which,
+                btn: button as i32,
+                down: false,
+            },
+            Event::ControllerButtonDown { which, button, .. } => Self::GamepadButton {
+                id: which,
+                btn: button as i32,
+                down: true,
+            },
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => Self::GamepadAxis {
+                id: which,
+                axis: axis as i32,
+                value,
+            },
+            _ => return None,
+        };
+
+        Some(input)
+    }
+}
+
+/// Everything that `Events::scan` read in a single frame
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InputFrame {
+    pub now_ms: u64,
+    pub delta_ms: u64,
+    pub mouse_pos: ScreenPoint,
+    pub events: Vec<InputEvent>,
+}
+
+impl InputFrame {
+    pub fn new() -> Self {
+        Self {
+            now_ms: 0,
+            delta_ms: 0,
+            mouse_pos: ScreenPoint::origin(),
+            events: Vec::new_in(GlobalAllocator),
+        }
+    }
+}
+
+impl Default for InputFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes input frames to a file, one JSON object per line
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &InputFrame) -> Result<(), RecordingError> {
+        serde_json::to_writer(&mut self.writer, frame)?;
+        writeln!(self.writer)?;
+        // the game can exit without dropping the recorder, e.g. when it crashes, which
+        // is when the recording is needed the most
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads back the input frames written by a `Recorder`
+pub struct Player {
+    lines: Lines<BufReader<File>>,
+}
+
+impl Player {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Ok(Self {
+            lines: BufReader::new(File::open(path)?).lines(),
+        })
+    }
+
+    /// Read the next frame, or `None` when the recording is over
+    pub fn next_frame(&mut self) -> Option<Result<InputFrame, RecordingError>> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err.into())),
+        };
+
+        Some(serde_json::from_str(&line).map_err(RecordingError::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_frame_roundtrip() {
+        let mut frame = InputFrame::new();
+        frame.now_ms = 1234;
+        frame.delta_ms = 16;
+        frame.mouse_pos = ScreenPoint::new(10.0, 20.5);
+        frame.events.push(InputEvent::Key {
+            scancode: 26,
+            down: true,
+            mods: 1,
+        });
+        frame.events.push(InputEvent::TextInput {
+            text: "zorb".to_owned(),
+        });
+
+        let line = serde_json::to_string(&frame).unwrap();
+        assert!(!line.contains('\n'));
+
+        let read: InputFrame = serde_json::from_str(&line).unwrap();
+        assert_eq!(read, frame);
+    }
+}
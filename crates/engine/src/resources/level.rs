@@ -0,0 +1,514 @@
+use std::{fmt::Write, path::Path, path::PathBuf};
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    coords::ChunkPoint,
+    tile_map::{
+        TileMap,
+        chunked::{CHUNK_SIZE, ChunkedTileMap},
+    },
+};
+
+use super::{
+    manager::{Resource, ResourceError, ResourceLoader, ResourceManager},
+    tile_kinds::{TileKindId, TileKinds},
+};
+
+/// The version written to level files. Bump it whenever the format changes
+pub const LEVEL_VERSION: u16 = 1;
+
+/// The first bytes of a binary level file
+const LEVEL_MAGIC: &[u8; 4] = b"LVL\0";
+
+/// The extension that selects the JSON form of a level instead of the binary one
+const JSON_EXTENSION: &str = "json";
+
+impl<A: Allocator + Clone> TileMap<TileKindId, A> {
+    /// Append the tiles in row-major order as `(run length, kind)` byte pairs
+    pub fn encode_rle<B: Allocator>(&self, out: &mut Vec<u8, B>) {
+        let mut run: Option<(u8, TileKindId)> = None;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let kind = *self.get(x, y);
+                run = match run {
+                    Some((len, prev)) if prev == kind && len < u8::MAX => Some((len + 1, kind)),
+                    Some((len, prev)) => {
+                        out.extend([len, prev.0]);
+                        Some((1, kind))
+                    }
+                    None => Some((1, kind)),
+                };
+            }
+        }
+        if let Some((len, kind)) = run {
+            out.extend([len, kind.0]);
+        }
+    }
+
+    /// Read the tiles written by `encode_rle` and return the bytes after them,
+    /// or `None` if they're malformed
+    pub fn decode_rle<'b>(&mut self, mut bytes: &'b [u8]) -> Option<&'b [u8]> {
+        let width = self.width();
+        let mut i = 0;
+        while i < width * self.height() {
+            let [len, kind, rest @ ..] = bytes else {
+                return None;
+            };
+            if *len == 0 || i + *len as usize > width * self.height() {
+                return None;
+            }
+
+            for _ in 0..*len {
+                self.set(i % width, i / width, TileKindId(*kind));
+                i += 1;
+            }
+            bytes = rest;
+        }
+        Some(bytes)
+    }
+
+    /// Get each row as a string with two hex digits per tile, which keeps diffs readable
+    pub fn to_hex_rows(&self) -> impl Iterator<Item = String> {
+        (0..self.height()).map(|y| {
+            (0..self.width()).fold(String::new(), |mut row, x| {
+                let _ = write!(row, "{:02x}", self.get(x, y).0);
+                row
+            })
+        })
+    }
+
+    /// Read the rows written by `to_hex_rows`, or return `None` if they're malformed
+    pub fn read_hex_rows<S: AsRef<str>>(&mut self, rows: &[S]) -> Option<()> {
+        if rows.len() != self.height() {
+            return None;
+        }
+
+        for (y, row) in rows.iter().enumerate() {
+            let row = row.as_ref();
+            if row.len() != self.width() * 2 {
+                return None;
+            }
+
+            for x in 0..self.width() {
+                let kind = u8::from_str_radix(row.get(x * 2..x * 2 + 2)?, 16).ok()?;
+                self.set(x, y, TileKindId(kind));
+            }
+        }
+        Some(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedChunk {
+    x: i32,
+    y: i32,
+    rows: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedLayer {
+    name: String,
+    chunks: Vec<SerializedChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "level", tag = "type")]
+struct SerializedLevel {
+    version: u16,
+    layers: Vec<SerializedLayer>,
+}
+
+/// A named layer of tiles in a level
+pub struct LevelLayer<A: Allocator + Clone> {
+    pub name: String,
+    pub tiles: ChunkedTileMap<TileKindId, A>,
+}
+
+/// Tile layers that can be saved to and loaded from a file.
+///
+/// There are two forms of the same data:
+/// - Binary: a magic number and version, then each layer's name and chunks, where each
+///   chunk is its coordinates followed by its run-length encoded tiles
+/// - JSON: each chunk is a list of hex rows so that edits show up nicely in diffs
+pub struct Level<A: Allocator + Clone> {
+    allocator: A,
+    layers: Vec<LevelLayer<A>, A>,
+}
+
+/// Just to use Ids without messing with lifetimes
+pub struct LevelIdMarker;
+
+impl<A: Allocator + Clone> Resource<'_> for Level<A> {
+    type Id = LevelIdMarker;
+}
+
+/// Reads the binary form of a level
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ResourceError> {
+        let Some((taken, rest)) = self.bytes.split_first_chunk::<N>() else {
+            return Err(ResourceError::Invalid("level is truncated"));
+        };
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'b [u8], ResourceError> {
+        if self.bytes.len() < len {
+            return Err(ResourceError::Invalid("level is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+impl<A: Allocator + Clone> Level<A> {
+    pub fn new_in(allocator: A) -> Self {
+        Self {
+            layers: Vec::new_in(allocator.clone()),
+            allocator,
+        }
+    }
+
+    /// Add a layer on top of the existing ones
+    pub fn push_layer(&mut self, name: &str, tiles: ChunkedTileMap<TileKindId, A>) {
+        self.layers.push(LevelLayer {
+            name: name.to_owned(),
+            tiles,
+        });
+    }
+
+    /// Get a layer's tiles by its name
+    pub fn layer(&self, name: &str) -> Option<&ChunkedTileMap<TileKindId, A>> {
+        self.layers
+            .iter()
+            .find(|l| l.name == name)
+            .map(|l| &l.tiles)
+    }
+
+    /// Iter over the layers from bottom to top
+    pub fn layers(&self) -> impl Iterator<Item = &LevelLayer<A>> {
+        self.layers.iter()
+    }
+
+    /// Check that every tile is of a kind in the registry. Levels are read without one,
+    /// and looking up an unknown kind panics
+    pub fn check_kinds<KA: Allocator + Clone>(
+        &self,
+        kinds: &TileKinds<KA>,
+    ) -> Result<(), ResourceError> {
+        let all_known = self
+            .layers
+            .iter()
+            .all(|layer| layer.tiles.iter().all(|(_, kind)| kinds.contains(*kind)));
+        if !all_known {
+            return Err(ResourceError::Invalid("level has tiles of unknown kinds"));
+        }
+        Ok(())
+    }
+
+    /// Get a layer's chunks sorted by position, so saving the same level twice gives
+    /// the same file
+    fn sorted_chunks(
+        layer: &LevelLayer<A>,
+    ) -> impl Iterator<Item = (ChunkPoint, &TileMap<TileKindId, A>)> {
+        let mut chunks: Vec<_> = layer.tiles.chunks().collect();
+        chunks.sort_by_key(|(p, _)| (p.y, p.x));
+        chunks.into_iter()
+    }
+
+    fn new_chunk(&self) -> TileMap<TileKindId, A> {
+        TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, self.allocator.clone())
+    }
+
+    /// Write the binary form of the level. Fails if there are more than 65535 layers or
+    /// a layer's name is longer than 255 bytes, since their counts are stored in two
+    /// bytes and one byte
+    pub fn encode<B: Allocator>(&self, out: &mut Vec<u8, B>) -> Result<(), ResourceError> {
+        out.extend_from_slice(LEVEL_MAGIC);
+        out.extend(LEVEL_VERSION.to_le_bytes());
+        let layer_count =
+            u16::try_from(self.layers.len()).or(Err(ResourceError::Invalid("too many layers")))?;
+        out.extend(layer_count.to_le_bytes());
+
+        for layer in &self.layers {
+            let name_len = u8::try_from(layer.name.len())
+                .or(Err(ResourceError::Invalid("layer name is too long")))?;
+            out.push(name_len);
+            out.extend_from_slice(layer.name.as_bytes());
+            out.extend((layer.tiles.chunks().count() as u32).to_le_bytes());
+
+            for (p, chunk) in Self::sorted_chunks(layer) {
+                out.extend(p.x.to_le_bytes());
+                out.extend(p.y.to_le_bytes());
+                chunk.encode_rle(out);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the binary form of a level
+    pub fn decode_in(allocator: A, bytes: &[u8]) -> Result<Self, ResourceError> {
+        let mut level = Self::new_in(allocator);
+        let mut reader = Reader { bytes };
+
+        if &reader.take::<4>()? != LEVEL_MAGIC {
+            return Err(ResourceError::Invalid("not a level file"));
+        }
+        if u16::from_le_bytes(reader.take()?) != LEVEL_VERSION {
+            return Err(ResourceError::Invalid("unsupported level version"));
+        }
+
+        let layer_count = u16::from_le_bytes(reader.take()?);
+        for _ in 0..layer_count {
+            let [name_len] = reader.take()?;
+            let name = std::str::from_utf8(reader.take_slice(name_len as usize)?)
+                .or(Err(ResourceError::Invalid("layer name is not UTF-8")))?;
+
+            let mut tiles = ChunkedTileMap::new_in(level.allocator.clone());
+            let chunk_count = u32::from_le_bytes(reader.take()?);
+            for _ in 0..chunk_count {
+                let x = i32::from_le_bytes(reader.take()?);
+                let y = i32::from_le_bytes(reader.take()?);
+
+                let mut chunk = level.new_chunk();
+                reader.bytes = chunk
+                    .decode_rle(reader.bytes)
+                    .ok_or(ResourceError::Invalid("malformed chunk"))?;
+                tiles.insert_chunk(ChunkPoint::new(x, y), chunk);
+            }
+
+            level.push_layer(name, tiles);
+        }
+
+        Ok(level)
+    }
+
+    /// Write the JSON form of the level
+    pub fn to_json(&self) -> Result<String, ResourceError> {
+        let serialized = SerializedLevel {
+            version: LEVEL_VERSION,
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| SerializedLayer {
+                    name: layer.name.clone(),
+                    chunks: Self::sorted_chunks(layer)
+                        .map(|(p, chunk)| SerializedChunk {
+                            x: p.x,
+                            y: p.y,
+                            rows: chunk.to_hex_rows().collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&serialized).or(Err(ResourceError::SaveFailed))
+    }
+
+    /// Read the JSON form of a level
+    pub fn from_json_in(allocator: A, json: &str) -> Result<Self, ResourceError> {
+        let serialized: SerializedLevel =
+            serde_json::from_str(json).or(Err(ResourceError::LoadFailed))?;
+        if serialized.version != LEVEL_VERSION {
+            return Err(ResourceError::Invalid("unsupported level version"));
+        }
+
+        let mut level = Self::new_in(allocator);
+        for layer in serialized.layers {
+            let mut tiles = ChunkedTileMap::new_in(level.allocator.clone());
+            for chunk in layer.chunks {
+                let mut tile_chunk = level.new_chunk();
+                tile_chunk
+                    .read_hex_rows(&chunk.rows)
+                    .ok_or(ResourceError::Invalid("malformed chunk"))?;
+                tiles.insert_chunk(ChunkPoint::new(chunk.x, chunk.y), tile_chunk);
+            }
+            level.push_layer(&layer.name, tiles);
+        }
+
+        Ok(level)
+    }
+
+    /// Save the level to a file, in JSON form if its extension is `.json`
+    /// and in binary form otherwise
+    pub fn save(&self, path: &Path) -> Result<(), ResourceError> {
+        if path.extension().is_some_and(|e| e == JSON_EXTENSION) {
+            let json = self.to_json()?;
+            std::fs::write(path, json).or(Err(ResourceError::SaveFailed))
+        } else {
+            let mut bytes = Vec::new_in(self.allocator.clone());
+            self.encode(&mut bytes)?;
+            std::fs::write(path, bytes).or(Err(ResourceError::SaveFailed))
+        }
+    }
+}
+
+/// Loads a `Level` from a file.
+///
+/// Unlike other resources the key includes the extension, which picks the form:
+/// `levels/start.lvl` is binary and `levels/start.lvl.json` is JSON.
+pub struct LevelLoader<A: Allocator + Clone> {
+    pub(super) root_path: PathBuf,
+
+    allocator: A,
+}
+
+impl<A: Allocator + Clone> LevelLoader<A> {
+    pub fn new(allocator: A, root_path: impl Into<PathBuf>) -> Self {
+        Self {
+            allocator,
+            root_path: root_path.into(),
+        }
+    }
+}
+
+impl<'l, 'res, A: Allocator + Clone> ResourceLoader<'l, 'res, Level<A>> for LevelLoader<A> {
+    fn load(&'l self, path: &'_ str) -> Result<Level<A>, ResourceError> {
+        let full_path = self.root_path.join(path);
+
+        if full_path.extension().is_some_and(|e| e == JSON_EXTENSION) {
+            let json = std::fs::read_to_string(full_path).or(Err(ResourceError::LoadFailed))?;
+            Level::from_json_in(self.allocator.clone(), &json)
+        } else {
+            let bytes = std::fs::read(full_path).or(Err(ResourceError::LoadFailed))?;
+            Level::decode_in(self.allocator.clone(), &bytes)
+        }
+    }
+}
+
+/// A resource manager for `Level`
+pub type LevelManager<'res, A> = ResourceManager<'res, 'res, Level<A>, LevelLoader<A>, A>;
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+    use crate::coords::TilePoint;
+
+    fn test_level() -> Level<Global> {
+        let mut ground = ChunkedTileMap::new_in(Global);
+        let mut walls = ChunkedTileMap::new_in(Global);
+        for v in -40..40 {
+            ground.set(TilePoint::new(v, v / 2), TileKindId(1));
+            walls.set(TilePoint::new(3, v), TileKindId(2));
+        }
+        walls.set(TilePoint::new(-100, 100), TileKindId(255));
+
+        let mut level = Level::new_in(Global);
+        level.push_layer("ground", ground);
+        level.push_layer("walls", walls);
+        level
+    }
+
+    fn assert_same_layers(a: &Level<Global>, b: &Level<Global>) {
+        assert_eq!(a.layers().count(), b.layers().count());
+        for (la, lb) in a.layers().zip(b.layers()) {
+            assert_eq!(la.name, lb.name);
+            assert_eq!(la.tiles.chunks().count(), lb.tiles.chunks().count());
+            for (p, tile) in la.tiles.iter() {
+                assert_eq!(tile, lb.tiles.get(p), "{} at {p:?}", la.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let mut map = TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, Global);
+        map.set(0, 0, TileKindId(3));
+        map.set(31, 31, TileKindId(4));
+
+        let mut bytes = Vec::new_in(Global);
+        map.encode_rle(&mut bytes);
+        // 1 + 1022 + 1 tiles where the middle run is split in 255 long runs
+        assert_eq!(bytes.len(), 2 * 7);
+
+        let mut decoded = TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, Global);
+        assert_eq!(decoded.decode_rle(&bytes), Some(&[][..]));
+        assert_eq!(*decoded.get(0, 0), TileKindId(3));
+        assert_eq!(*decoded.get(31, 31), TileKindId(4));
+        assert_eq!(*decoded.get(5, 5), TileKindId::EMPTY);
+
+        assert_eq!(decoded.decode_rle(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let level = test_level();
+        let mut bytes = Vec::new_in(Global);
+        level.encode(&mut bytes).unwrap();
+
+        let decoded = Level::decode_in(Global, &bytes).unwrap();
+        assert_same_layers(&level, &decoded);
+
+        bytes[4] = 99;
+        assert!(Level::decode_in(Global, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_reject_long_layer_name() {
+        let mut level = test_level();
+        level.push_layer(&"a".repeat(255), ChunkedTileMap::new_in(Global));
+        let mut bytes = Vec::new_in(Global);
+        level.encode(&mut bytes).unwrap();
+        assert_same_layers(&level, &Level::decode_in(Global, &bytes).unwrap());
+
+        level.push_layer(&"a".repeat(256), ChunkedTileMap::new_in(Global));
+        assert!(matches!(
+            level.encode(&mut Vec::new_in(Global)),
+            Err(ResourceError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_too_many_layers() {
+        let mut level = Level::new_in(Global);
+        for _ in 0..=u16::MAX as usize {
+            level.push_layer("ground", ChunkedTileMap::new_in(Global));
+        }
+        assert!(matches!(
+            level.encode(&mut Vec::new_in(Global)),
+            Err(ResourceError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let level = test_level();
+        let json = level.to_json().unwrap();
+
+        let decoded = Level::from_json_in(Global, &json).unwrap();
+        assert_same_layers(&level, &decoded);
+        assert_eq!(decoded.to_json().unwrap(), json);
+    }
+
+    #[test]
+    fn test_check_kinds() {
+        let kinds = TileKinds::from_json_in(
+            Global,
+            r#"{ "type": "tile_kinds", "kinds": [
+                { "id": 1, "name": "floor" },
+                { "id": 2, "name": "wall" }
+            ] }"#,
+        )
+        .unwrap();
+
+        let mut level = test_level();
+        assert!(matches!(
+            level.check_kinds(&kinds),
+            Err(ResourceError::Invalid(_))
+        ));
+
+        level.layers.retain(|l| l.name == "ground");
+        assert!(level.check_kinds(&kinds).is_ok());
+    }
+}
@@ -13,6 +13,10 @@ use crate::types::Id;
 pub enum ResourceError {
     #[error("Resource could not be loaded")]
     LoadFailed,
+    #[error("Resource could not be saved")]
+    SaveFailed,
+    #[error("Resource is invalid: {0}")]
+    Invalid(&'static str),
 }
 
 /// A resource that can be identified by an ID
@@ -3,16 +3,26 @@ use std::path::PathBuf;
 use allocator_api2::alloc::Allocator;
 use sdl3::video::WindowContext;
 
+pub mod level;
 pub mod manager;
 pub mod sprite_map;
+pub mod tile_kinds;
+pub mod tiled;
 
 /// Holds all resource managers
 pub struct Resources<'res, A: Allocator + Clone> {
     pub sprites: sprite_map::SpriteMapManager<'res, WindowContext, A>,
+    pub tile_kinds: tile_kinds::TileKindsManager<'res, A>,
+    pub levels: level::LevelManager<'res, A>,
+    pub tiled_maps: tiled::TiledMapManager<'res, A>,
 }
 
 impl<'res, A: Allocator + Clone> Resources<'res, A> {
     pub fn set_root(&mut self, root: impl Into<PathBuf>) {
-        self.sprites.loader.root_path = root.into();
+        let root = root.into();
+        self.tile_kinds.loader.root_path = root.clone();
+        self.levels.loader.root_path = root.clone();
+        self.tiled_maps.loader.root_path = root.clone();
+        self.sprites.loader.root_path = root;
     }
 }
@@ -11,8 +11,12 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     animation::{Animation, AnimationCursor, Keyframe},
+    coords::TilePoint,
     serde::{is_empty, ordered_map},
-    tile_map::{self, NeighborMask},
+    tile_map::{
+        NeighborMask,
+        layout::{TileLayout, pick_variant, tile_hash},
+    },
     types::Id,
 };
 
@@ -246,7 +250,7 @@ impl SpriteMapAnimation {
             .into_iter()
             .map(|(frame_i, (duration, layers))| (frame_i, Keyframe::new(duration, layers)))
             .collect();
-        keyframe_tuples.sort_by(|a, b| a.0.cmp(&b.0));
+        keyframe_tuples.sort_by_key(|a| a.0);
         let mut keyframes: Vec<_> = keyframe_tuples
             .into_iter()
             .map(|(_i, layers)| layers)
@@ -275,20 +279,33 @@ impl SpriteMapAnimation {
 /// A set of tiles, where each cel has implicit information about how to connect to
 /// its neighbors depending on the position on the grid.
 ///
-/// It stores the tile size and the cel where all the subtiles are stored. This cel
-/// must be exactly 12x4 tiles in size, and the tiles must be arranged according to
-/// the mask.
+/// It stores the tile size and the cel where all the subtiles are stored. The size of
+/// the cel and how the tiles are arranged in it depend on the layout.
+///
+/// The cel can hold variants of every tile as copies of the layout stacked below each
+/// other, which are picked from each tile's position.
+///
+/// Animated tilesets name an animation of the sprite map that has the cel in its first
+/// frame. All their tiles then show the same frame at the same time.
 #[derive(Serialize, Deserialize)]
 pub struct Tileset {
     grid_size: u8,
     cel: u16,
+    #[serde(default)]
+    layout: TileLayout,
+    /// The relative weight of each variant, or empty if there's only one
+    #[serde(default, skip_serializing_if = "is_empty")]
+    weights: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    animation: Option<String>,
 }
 
-impl Tileset {
-    /// Get the index of the neighbor mask in a tileset
-    pub fn rect_of(neighbor_mask: NeighborMask) -> (u8, u8) {
-        tile_map::MASKS[neighbor_mask.0 as usize]
-    }
+/// The animation of a tileset, resolved when its sprite map is loaded
+#[derive(Copy, Clone)]
+struct TilesetAnimation {
+    anim: Id<SpriteMapAnimation>,
+    /// The index of the tileset's cels in the keyframes
+    layer: usize,
 }
 
 impl Resource<'_> for Tileset {
@@ -300,19 +317,35 @@ pub struct ResolvedTileset<'texowner, 'tex> {
     pub tex: &'texowner Texture<'tex>,
     pub rect: FRect,
     pub grid_size: u8,
+    pub layout: &'texowner TileLayout,
+    pub weights: &'texowner [u16],
 }
 
 impl<'texowner, 'tex> ResolvedTileset<'texowner, 'tex> {
-    /// Get the final rectangle in the sprite that can be used
-    /// to draw the appropriate tile according to its neighbor connections
-    pub fn tex_rect_for(&self, connected_neighbors: NeighborMask) -> FRect {
-        let tile_rect_pos = Tileset::rect_of(connected_neighbors);
-        FRect {
-            x: self.rect.x + (tile_rect_pos.0 * self.grid_size) as f32,
-            y: self.rect.y + (tile_rect_pos.1 * self.grid_size) as f32,
-            w: self.grid_size as f32,
-            h: self.grid_size as f32,
-        }
+    /// Pick the variant drawn at a tile, which is always the same for that tile
+    pub fn variant_at(&self, p: TilePoint) -> usize {
+        pick_variant(self.weights, tile_hash(p))
+    }
+
+    /// Get the rectangles in the sprite that can be used to draw the appropriate tile
+    /// according to its neighbor connections, each with where it goes within the tile
+    /// in fractions of a tile
+    pub fn tex_rects_for(
+        &self,
+        connected_neighbors: NeighborMask,
+        variant: usize,
+    ) -> impl Iterator<Item = (FRect, FRect)> {
+        let grid_size = self.grid_size as f32;
+        let variant_y = (variant * self.layout.size().1 as usize) as f32;
+        self.layout.parts(connected_neighbors).map(move |part| {
+            let tex_rect = FRect {
+                x: self.rect.x + (part.tile.0 as f32 + part.src.x) * grid_size,
+                y: self.rect.y + (variant_y + part.tile.1 as f32 + part.src.y) * grid_size,
+                w: part.src.w * grid_size,
+                h: part.src.h * grid_size,
+            };
+            (tex_rect, part.dst)
+        })
     }
 }
 
@@ -358,12 +391,16 @@ pub struct SpriteMap<'tex, A: Allocator> {
     id: Id<SpriteMapIdMarker>,
     pub tex: Texture<'tex>,
     pub cels: Vec<SpriteMapCel, A>,
+    /// The area covered by every cel, relative to their source material
+    bounds: FRect,
 
     animation_names: HashMap<String, Id<SpriteMapAnimation>, DefaultHashBuilder, A>,
     animations: Vec<SpriteMapAnimation, A>,
 
     tileset_names: HashMap<String, Id<Tileset>, DefaultHashBuilder, A>,
     tilesets: Vec<Tileset, A>,
+    /// The animation of each tileset, if it has one
+    tileset_animations: Vec<Option<TilesetAnimation>, A>,
 }
 
 /// Just to use Ids without messing with lifetimes
@@ -401,23 +438,71 @@ impl<'tex, A: Allocator + Clone> SpriteMap<'tex, A> {
         id: Id<SpriteMapIdMarker>,
         tex: Texture<'tex>,
         metadata: SerializedSpriteMap,
-    ) -> Self {
+    ) -> Result<Self, ResourceError> {
         let (animation_names, animations) =
             Self::sort_names_and_ids(&allocator, id, metadata.animations);
         let (tileset_names, tilesets) = Self::sort_names_and_ids(&allocator, id, metadata.tilesets);
 
+        let mut tileset_animations = Vec::with_capacity_in(tilesets.len(), allocator.clone());
+        for tileset in &tilesets {
+            let Some(anim_name) = &tileset.animation else {
+                tileset_animations.push(None);
+                continue;
+            };
+
+            let anim = *animation_names
+                .get(anim_name)
+                .ok_or(ResourceError::Invalid("unknown tileset animation"))?;
+            let layer = animations[anim.lo() as usize].keyframes.keyframes()[0]
+                .value
+                .iter()
+                .position(|cel| *cel == tileset.cel)
+                .ok_or(ResourceError::Invalid(
+                    "tileset cel is not in the first frame of its animation",
+                ))?;
+            let keyframes = animations[anim.lo() as usize].keyframes.keyframes();
+            if keyframes
+                .iter()
+                .any(|keyframe| keyframe.value.len() <= layer)
+            {
+                return Err(ResourceError::Invalid(
+                    "tileset layer is missing from a frame of its animation",
+                ));
+            }
+            tileset_animations.push(Some(TilesetAnimation { anim, layer }));
+        }
+
         let mut cels = Vec::with_capacity_in(metadata.cels.len(), allocator);
         cels.extend(metadata.cels);
 
-        Self {
+        let bounds = cels
+            .iter()
+            .map(|cel| cel.src_rect)
+            .reduce(|a, b| {
+                let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+                let right = (a.x + a.w).max(b.x + b.w);
+                let bottom = (a.y + a.h).max(b.y + b.h);
+                FRect::new(x, y, right - x, bottom - y)
+            })
+            .unwrap_or(FRect::new(0.0, 0.0, 0.0, 0.0));
+
+        Ok(Self {
             id,
             tex,
             cels,
+            bounds,
             animations,
             animation_names,
             tilesets,
             tileset_names,
-        }
+            tileset_animations,
+        })
+    }
+
+    /// The area covered by every cel, relative to their source material, which is what
+    /// a sprite made out of them is drawn within
+    pub fn bounds(&self) -> FRect {
+        self.bounds
     }
 
     /// Get an animation's ID by its name or panic
@@ -442,16 +527,46 @@ impl<'tex, A: Allocator + Clone> SpriteMap<'tex, A> {
             .unwrap_or_else(|| panic!("Invalid tileset '{tileset_name}'"))
     }
 
-    /// Get a tileset by ID or panic
+    /// Get a tileset's ID by its name if it exists
+    pub fn find_tileset_id(&self, tileset_name: &str) -> Option<Id<Tileset>> {
+        self.tileset_names.get(tileset_name).copied()
+    }
+
+    /// Get a tileset by ID or panic. Animated tilesets are on their first frame
     pub fn get_tileset<'this>(&'this self, id: Id<Tileset>) -> ResolvedTileset<'this, 'tex> {
+        self.get_tileset_at(id, 0)
+    }
+
+    /// Whether a tileset changes over time, so what's drawn with it can't be kept
+    /// between frames
+    pub fn is_tileset_animated(&self, id: Id<Tileset>) -> bool {
+        debug_assert!(id.hi() == self.id.full() as u16);
+        self.tileset_animations[id.lo() as usize].is_some()
+    }
+
+    /// Get a tileset by ID or panic, on the frame of its animation at a time if it has
+    /// one
+    pub fn get_tileset_at<'this>(
+        &'this self,
+        id: Id<Tileset>,
+        now_ms: u64,
+    ) -> ResolvedTileset<'this, 'tex> {
         debug_assert!(id.hi() == self.id.full() as u16);
         let tileset = &self.tilesets[id.lo() as usize];
 
-        let rect = self.cels[tileset.cel as usize].tex_rect;
+        let cel = match self.tileset_animations[id.lo() as usize] {
+            Some(TilesetAnimation { anim, layer }) => {
+                self.get_animation(anim).keyframes.value_at(now_ms)[layer]
+            }
+            None => tileset.cel,
+        };
+        let rect = self.cels[cel as usize].tex_rect;
         ResolvedTileset {
             tex: &self.tex,
             rect,
             grid_size: tileset.grid_size,
+            layout: &tileset.layout,
+            weights: &tileset.weights,
         }
     }
 }
@@ -513,6 +628,18 @@ pub fn ase_to_res<A: Allocator + Clone>(
         }
     }
 
+    let mut existing_layouts: HashMap<String, TileLayout> = std::fs::read_to_string(&res_path)
+        .ok()
+        .and_then(|res_str| serde_json::from_str::<SerializedSpriteMap>(&res_str).ok())
+        .map(|res| {
+            res.tilesets
+                .into_iter()
+                .filter(|(_, tileset)| matches!(tileset.layout, TileLayout::Custom { .. }))
+                .map(|(name, tileset)| (name, tileset.layout))
+                .collect()
+        })
+        .unwrap_or_default();
+
     // Find tileset layers
     let tilesets = {
         let tileset_layers: Vec<_> = metadata
@@ -535,27 +662,91 @@ pub fn ase_to_res<A: Allocator + Clone>(
                     })
                     .collect();
 
-                assert_eq!(cels.len(), 1);
+                let tags = layer.tags();
+
+                // animated tilesets have one cel per frame, which must all be the same size
+                let animation = tags.get("animation").map(|name| name.to_string());
+                match &animation {
+                    Some(name) => {
+                        if !metadata.meta.animations.iter().any(|a| a.name == *name) {
+                            return Err(format!(
+                                "Unknown animation '{name}' for tileset {}",
+                                layer.name
+                            ));
+                        }
+                        if cels.is_empty()
+                            || cels.iter().any(|(_, c)| {
+                                c.source_rect.w != cels[0].1.source_rect.w
+                                    || c.source_rect.h != cels[0].1.source_rect.h
+                            })
+                        {
+                            return Err(format!(
+                                "Frames of animated tileset {} must all be the same size",
+                                layer.name
+                            ));
+                        }
+                    }
+                    None if cels.len() != 1 => {
+                        return Err(format!(
+                            "Tileset {} must have a single cel, or be animated",
+                            layer.name
+                        ));
+                    }
+                    None => {}
+                }
 
                 let (cel_i, cel) = cels[0];
 
-                let grid_size = layer
-                    .tags()
+                let grid_size = tags
                     .get("tile-size")
                     .map(|s| s.parse::<u8>().unwrap())
                     .unwrap();
 
-                assert_eq!(cel.source_rect.w, 12 * grid_size as u16);
-                assert_eq!(cel.source_rect.h, 4 * grid_size as u16);
+                // custom layouts can only be written in the `.res.json`, so keep them
+                let layout = match tags.get("layout") {
+                    Some(kind) => serde_json::from_value(serde_json::json!({ "kind": kind }))
+                        .map_err(|_| {
+                            format!("Invalid layout '{kind}' for tileset {}", layer.name)
+                        })?,
+                    None => existing_layouts.remove(&layer.name).unwrap_or_default(),
+                };
+                layout
+                    .validate()
+                    .map_err(|err| format!("Tileset {}: {err}", layer.name))?;
+
+                // variants are stacked below each other, like `weights=4:1:1`
+                let weights: Vec<u16> = match tags.get("weights") {
+                    Some(weights) => weights
+                        .split(":")
+                        .map(|w| w.parse::<u16>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| format!("Invalid weights for tileset {}", layer.name))?,
+                    None => Vec::new(),
+                };
+                let variants = weights.len().max(1) as u16;
+
+                let (width, height) = layout.size();
+                if cel.source_rect.w != width as u16 * grid_size as u16
+                    || cel.source_rect.h != variants * height as u16 * grid_size as u16
+                {
+                    return Err(format!(
+                        "Tileset {} is {}x{} pixels but its {layout:?} layout needs {variants} \
+                         variants of {width}x{height} tiles of {grid_size} pixels",
+                        layer.name, cel.source_rect.w, cel.source_rect.h
+                    ));
+                }
 
                 let tileset = Tileset {
                     cel: cel_i as u16,
                     grid_size,
+                    layout,
+                    weights,
+                    animation,
                 };
 
-                (layer.name.clone(), tileset)
+                Ok((layer.name.clone(), tileset))
             })
-            .collect()
+            .collect::<Result<_, String>>()?
     };
 
     let sm = SerializedSpriteMap {
@@ -584,6 +775,25 @@ pub fn ase_to_res<A: Allocator + Clone>(
         tilesets,
     };
 
+    // animated tilesets find their cel at the same index in every frame
+    for (name, tileset) in &sm.tilesets {
+        let Some(anim_name) = &tileset.animation else {
+            continue;
+        };
+        let layer_index = |cels: &[u16]| {
+            cels.iter()
+                .position(|cel| split_cel_name(&metadata.cels[*cel as usize].name).2 == name)
+        };
+        let keyframes = sm.animations[anim_name].keyframes.keyframes();
+        let first = layer_index(&keyframes[0].value);
+        if first.is_none() || keyframes.iter().any(|k| layer_index(&k.value) != first) {
+            return Err(format!(
+                "Tileset {name} must have a cel in every frame of animation '{anim_name}', \
+                 with the same layers below it"
+            ));
+        }
+    }
+
     let res_str = serde_json::to_string_pretty(&sm).map_err(|err| err.to_string())?;
     std::fs::write(res_path, res_str).map_err(|err| err.to_string())?;
 
@@ -626,6 +836,9 @@ where
         let res_str = std::fs::read_to_string(res_path).or(Err(ResourceError::LoadFailed))?;
         let res: SerializedSpriteMap =
             serde_json::from_str(&res_str).or(Err(ResourceError::LoadFailed))?;
+        for tileset in res.tilesets.values() {
+            tileset.layout.validate().map_err(ResourceError::Invalid)?;
+        }
 
         let tex_full_path = self.root_path.join(&res.tex_path);
 
@@ -635,7 +848,7 @@ where
             .or(Err(ResourceError::LoadFailed))?;
         tex.set_scale_mode(ScaleMode::Nearest);
 
-        let sm = SpriteMap::new_in(self.allocator.clone(), self.next_id.get(), tex, res);
+        let sm = SpriteMap::new_in(self.allocator.clone(), self.next_id.get(), tex, res)?;
 
         let next = self.next_id.get().next();
         self.next_id.set(next);
@@ -0,0 +1,314 @@
+use std::path::PathBuf;
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use hashbrown::{DefaultHashBuilder, HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    coords::TilePoint,
+    tile_map::{NeighborMask, TileMap, chunked::ChunkedTileMap},
+};
+
+use super::manager::{Resource, ResourceError, ResourceLoader, ResourceManager};
+
+/// The maximum number of tile kinds in a registry, including `TileKindId::EMPTY`
+pub const MAX_TILE_KINDS: usize = u8::MAX as usize + 1;
+
+/// The compact ID of a tile kind, which is what tile maps store
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TileKindId(pub u8);
+
+impl TileKindId {
+    /// The absence of a tile. Every registry has it
+    pub const EMPTY: Self = Self(0);
+
+    pub fn is_empty(self) -> bool {
+        self == Self::EMPTY
+    }
+}
+
+fn default_cost() -> f32 {
+    1.0
+}
+
+/// The properties shared by all the tiles of one kind
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TileKind {
+    pub id: TileKindId,
+    pub name: String,
+    /// The name of the tileset used to draw this kind, if it has its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tileset: Option<String>,
+    #[serde(default)]
+    pub solid: bool,
+    /// How expensive it is to walk over this tile, where 1 is a plain floor and the
+    /// cheapest a tile can be, so distances stay admissible heuristics for pathfinding
+    #[serde(default = "default_cost")]
+    pub walk_cost: f32,
+    #[serde(default = "default_cost")]
+    pub friction: f32,
+    #[serde(default)]
+    pub destructible: bool,
+    #[serde(default)]
+    pub blocks_light: bool,
+}
+
+impl TileKind {
+    fn empty() -> Self {
+        Self {
+            id: TileKindId::EMPTY,
+            name: "empty".to_owned(),
+            tileset: None,
+            solid: false,
+            walk_cost: default_cost(),
+            friction: default_cost(),
+            destructible: false,
+            blocks_light: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "tile_kinds", tag = "type")]
+struct SerializedTileKinds {
+    kinds: Vec<TileKind>,
+}
+
+/// All the known tile kinds, indexed by their ID.
+///
+/// IDs in the JSON file must start at 1 and be contiguous, as 0 is reserved for
+/// `TileKindId::EMPTY`.
+pub struct TileKinds<A: Allocator> {
+    names: HashMap<String, TileKindId, DefaultHashBuilder, A>,
+    kinds: Vec<TileKind, A>,
+}
+
+/// Just to use Ids without messing with lifetimes
+pub struct TileKindsIdMarker;
+
+impl<A: Allocator + Clone> Resource<'_> for TileKinds<A> {
+    type Id = TileKindsIdMarker;
+}
+
+impl<A: Allocator + Clone> TileKinds<A> {
+    /// Parse a registry from its JSON form
+    pub fn from_json_in(allocator: A, json: &str) -> Result<Self, ResourceError> {
+        let serialized: SerializedTileKinds =
+            serde_json::from_str(json).or(Err(ResourceError::LoadFailed))?;
+        Self::new_in(allocator, serialized)
+    }
+
+    fn new_in(allocator: A, serialized: SerializedTileKinds) -> Result<Self, ResourceError> {
+        if serialized.kinds.len() >= MAX_TILE_KINDS {
+            return Err(ResourceError::Invalid("too many tile kinds"));
+        }
+
+        let mut kinds = Vec::with_capacity_in(serialized.kinds.len() + 1, allocator.clone());
+        let mut names = HashMap::new_in(allocator);
+
+        kinds.push(TileKind::empty());
+        for kind in serialized.kinds {
+            if kind.id.0 as usize != kinds.len() {
+                return Err(ResourceError::Invalid("tile kind IDs must be contiguous"));
+            }
+            if !kind.walk_cost.is_finite() || kind.walk_cost < 1.0 {
+                return Err(ResourceError::Invalid(
+                    "tile kind walk costs must be finite and at least 1",
+                ));
+            }
+            if !kind.friction.is_finite() || kind.friction < 0.0 {
+                return Err(ResourceError::Invalid(
+                    "tile kind frictions must be finite and not negative",
+                ));
+            }
+            if names.insert(kind.name.clone(), kind.id).is_some() {
+                return Err(ResourceError::Invalid("duplicate tile kind name"));
+            }
+            kinds.push(kind);
+        }
+
+        Ok(Self { names, kinds })
+    }
+
+    /// Get a kind's ID by its name if it exists
+    pub fn find_id(&self, name: &str) -> Option<TileKindId> {
+        self.names.get(name).copied()
+    }
+
+    /// Get a kind's ID by its name or panic
+    pub fn get_id(&self, name: &str) -> TileKindId {
+        self.find_id(name)
+            .unwrap_or_else(|| panic!("Invalid tile kind '{name}'"))
+    }
+
+    /// Whether a kind with this ID exists
+    pub fn contains(&self, id: TileKindId) -> bool {
+        (id.0 as usize) < self.kinds.len()
+    }
+
+    /// Get a kind by ID or panic
+    pub fn get(&self, id: TileKindId) -> &TileKind {
+        &self.kinds[id.0 as usize]
+    }
+
+    /// Iterate over all the kinds, starting with `TileKindId::EMPTY`
+    pub fn iter(&self) -> impl Iterator<Item = &TileKind> {
+        self.kinds.iter()
+    }
+
+    pub fn is_solid(&self, id: TileKindId) -> bool {
+        self.get(id).solid
+    }
+
+    pub fn blocks_light(&self, id: TileKindId) -> bool {
+        self.get(id).blocks_light
+    }
+
+    pub fn walk_cost(&self, id: TileKindId) -> f32 {
+        self.get(id).walk_cost
+    }
+}
+
+impl<A: Allocator + Clone> TileMap<TileKindId, A> {
+    /// Get the properties of the tile at a position
+    pub fn kind_at<'k, KA: Allocator + Clone>(
+        &self,
+        kinds: &'k TileKinds<KA>,
+        x: usize,
+        y: usize,
+    ) -> &'k TileKind {
+        kinds.get(*self.get(x, y))
+    }
+
+    /// Get the neighbors made of the same kind as the tile at a position,
+    /// so autotiling only connects tiles of the same material
+    pub fn same_kind_neighbors(&self, x: usize, y: usize) -> NeighborMask {
+        let kind = *self.get(x, y);
+        self.filter_neighbors(x, y, |t| *t == kind)
+    }
+}
+
+impl<A: Allocator + Clone> ChunkedTileMap<TileKindId, A> {
+    /// Get the properties of the tile at a position
+    pub fn kind_at<'k, KA: Allocator + Clone>(
+        &self,
+        kinds: &'k TileKinds<KA>,
+        p: TilePoint,
+    ) -> &'k TileKind {
+        kinds.get(*self.get(p))
+    }
+
+    /// Get the neighbors made of the same kind as the tile at a position,
+    /// so autotiling only connects tiles of the same material
+    pub fn same_kind_neighbors(&self, p: TilePoint) -> NeighborMask {
+        let kind = *self.get(p);
+        self.filter_neighbors(p, |t| *t == kind)
+    }
+}
+
+/// Loads `TileKinds` from a JSON file
+pub struct TileKindsLoader<A: Allocator + Clone> {
+    pub(super) root_path: PathBuf,
+
+    allocator: A,
+}
+
+impl<A: Allocator + Clone> TileKindsLoader<A> {
+    pub fn new(allocator: A, root_path: impl Into<PathBuf>) -> Self {
+        Self {
+            allocator,
+            root_path: root_path.into(),
+        }
+    }
+}
+
+impl<'l, 'res, A: Allocator + Clone> ResourceLoader<'l, 'res, TileKinds<A>> for TileKindsLoader<A> {
+    fn load(&'l self, path: &'_ str) -> Result<TileKinds<A>, ResourceError> {
+        let res_path = self.root_path.join(path).with_extension("res.json");
+        let res_str = std::fs::read_to_string(res_path).or(Err(ResourceError::LoadFailed))?;
+
+        TileKinds::from_json_in(self.allocator.clone(), &res_str)
+    }
+}
+
+/// A resource manager for `TileKinds`
+pub type TileKindsManager<'res, A> =
+    ResourceManager<'res, 'res, TileKinds<A>, TileKindsLoader<A>, A>;
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    const KINDS: &str = r#"{
+        "type": "tile_kinds",
+        "kinds": [
+            { "id": 1, "name": "floor" },
+            { "id": 2, "name": "wall", "tileset": "walls", "solid": true, "blocks_light": true },
+            { "id": 3, "name": "mud", "walk_cost": 3.0, "friction": 0.5 }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_kinds() {
+        let kinds = TileKinds::from_json_in(Global, KINDS).unwrap();
+
+        assert_eq!(kinds.iter().count(), 4);
+        assert_eq!(kinds.get(TileKindId::EMPTY).name, "empty");
+
+        let wall = kinds.get_id("wall");
+        assert_eq!(wall, TileKindId(2));
+        assert!(kinds.is_solid(wall));
+        assert!(kinds.blocks_light(wall));
+        assert_eq!(kinds.get(wall).tileset.as_deref(), Some("walls"));
+
+        let mud = kinds.get_id("mud");
+        assert!(!kinds.is_solid(mud));
+        assert_eq!(kinds.walk_cost(mud), 3.0);
+        assert_eq!(kinds.walk_cost(kinds.get_id("floor")), 1.0);
+        assert_eq!(kinds.find_id("lava"), None);
+    }
+
+    #[test]
+    fn test_reject_invalid_ids() {
+        let gap = r#"{ "type": "tile_kinds", "kinds": [{ "id": 2, "name": "floor" }] }"#;
+        assert!(TileKinds::from_json_in(Global, gap).is_err());
+
+        let dup = r#"{ "type": "tile_kinds", "kinds": [
+            { "id": 1, "name": "floor" },
+            { "id": 2, "name": "floor" }
+        ] }"#;
+        assert!(TileKinds::from_json_in(Global, dup).is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_costs() {
+        let cheap = r#"{ "type": "tile_kinds", "kinds": [
+            { "id": 1, "name": "ice", "walk_cost": 0.5 }
+        ] }"#;
+        assert!(TileKinds::from_json_in(Global, cheap).is_err());
+
+        // JSON has no infinity, but its numbers can still overflow an f32
+        let huge = r#"{ "type": "tile_kinds", "kinds": [
+            { "id": 1, "name": "tar", "walk_cost": 1e100 }
+        ] }"#;
+        assert!(TileKinds::from_json_in(Global, huge).is_err());
+
+        let slippery = r#"{ "type": "tile_kinds", "kinds": [
+            { "id": 1, "name": "oil", "friction": -1.0 }
+        ] }"#;
+        assert!(TileKinds::from_json_in(Global, slippery).is_err());
+    }
+
+    #[test]
+    fn test_same_kind_neighbors() {
+        let mut map = ChunkedTileMap::new_in(Global);
+        map.set(TilePoint::new(0, 0), TileKindId(1));
+        map.set(TilePoint::new(1, 0), TileKindId(1));
+        map.set(TilePoint::new(-1, 0), TileKindId(2));
+
+        let mask = map.same_kind_neighbors(TilePoint::new(0, 0));
+        assert_eq!(mask, NeighborMask(NeighborMask::RIGHT));
+    }
+}
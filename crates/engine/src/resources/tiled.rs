@@ -0,0 +1,703 @@
+use std::path::{Path, PathBuf};
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use euclid::{Point2D, Rect, Size2D};
+use hashbrown::{DefaultHashBuilder, HashMap};
+use serde::Deserialize;
+
+use crate::{
+    coords::{TilePoint, TileSpace},
+    tile_map::chunked::ChunkedTileMap,
+};
+
+use super::{
+    level::Level,
+    manager::{Resource, ResourceError, ResourceLoader, ResourceManager},
+    tile_kinds::{TileKindId, TileKinds},
+};
+
+/// Tiled stores flip and rotation flags in the high bits of each tile's global ID
+const GID_FLAGS_MASK: u32 = 0xF000_0000;
+
+/// The property that names the tile kind of a tile in a Tiled tileset
+const PROPERTY_KIND: &str = "kind";
+
+/// The value of a custom property
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Color(String),
+    File(String),
+    /// The ID of another object in the map
+    Object(u32),
+    Class(serde_json::Value),
+}
+
+impl PropertyValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Get a number, including integers
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Float(v) => Some(*v),
+            Self::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    /// Get any of the string-like values
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(v) | Self::Color(v) | Self::File(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+fn default_property_type() -> String {
+    "string".to_owned()
+}
+
+#[derive(Deserialize)]
+struct RawProperty {
+    name: String,
+    #[serde(rename = "type", default = "default_property_type")]
+    ty: String,
+    value: serde_json::Value,
+}
+
+/// The custom properties of a map, layer, tile or object
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "Vec<RawProperty>")]
+pub struct Properties(Vec<(String, PropertyValue)>);
+
+impl TryFrom<Vec<RawProperty>> for Properties {
+    type Error = String;
+
+    fn try_from(raw: Vec<RawProperty>) -> Result<Self, Self::Error> {
+        raw.into_iter()
+            .map(|p| {
+                let value = match p.ty.as_str() {
+                    "bool" => p.value.as_bool().map(PropertyValue::Bool),
+                    "int" => p.value.as_i64().map(PropertyValue::Int),
+                    "float" => p.value.as_f64().map(PropertyValue::Float),
+                    "string" => p
+                        .value
+                        .as_str()
+                        .map(|v| PropertyValue::String(v.to_owned())),
+                    "color" => p.value.as_str().map(|v| PropertyValue::Color(v.to_owned())),
+                    "file" => p.value.as_str().map(|v| PropertyValue::File(v.to_owned())),
+                    "object" => p.value.as_u64().map(|v| PropertyValue::Object(v as u32)),
+                    "class" => Some(PropertyValue::Class(p.value)),
+                    _ => None,
+                };
+                value
+                    .map(|v| (p.name, v))
+                    .ok_or_else(|| format!("Invalid value for {} property", p.ty))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Properties {
+    pub fn get(&self, name: &str) -> Option<&PropertyValue> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PropertyValue)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v))
+    }
+}
+
+/// The tiles of a layer, which are numbers until the encoding is known
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTileData {
+    Csv(Vec<u32>),
+    Encoded(#[expect(dead_code)] String),
+}
+
+impl Default for RawTileData {
+    fn default() -> Self {
+        Self::Csv(Vec::new_in(Default::default()))
+    }
+}
+
+#[derive(Deserialize)]
+struct RawChunk {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    data: RawTileData,
+}
+
+#[derive(Deserialize)]
+struct RawObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    /// Named `type` before Tiled 1.9 and `class` after
+    #[serde(rename = "type", alias = "class", default)]
+    class: String,
+    x: f64,
+    y: f64,
+    #[serde(default)]
+    width: f64,
+    #[serde(default)]
+    height: f64,
+    /// Only set for tile objects, which are placed by their bottom-left corner instead
+    /// of their top-left one
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Properties,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum RawLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        name: String,
+        #[serde(default)]
+        width: u32,
+        #[serde(default)]
+        height: u32,
+        #[serde(default)]
+        data: RawTileData,
+        /// Only used by infinite maps
+        #[serde(default)]
+        chunks: Vec<RawChunk>,
+        #[serde(default)]
+        properties: Properties,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        name: String,
+        #[serde(default)]
+        objects: Vec<RawObject>,
+    },
+    #[serde(rename = "group")]
+    Group {
+        #[serde(default)]
+        layers: Vec<RawLayer>,
+    },
+    #[serde(rename = "imagelayer")]
+    Image {},
+}
+
+#[derive(Deserialize)]
+struct RawTile {
+    id: u32,
+    #[serde(rename = "type", alias = "class", default)]
+    class: String,
+    #[serde(default)]
+    properties: Properties,
+}
+
+#[derive(Deserialize)]
+struct RawTileset {
+    #[serde(default)]
+    firstgid: u32,
+    /// Set when the tileset is in its own file
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tiles: Vec<RawTile>,
+}
+
+#[derive(Deserialize)]
+struct RawMap {
+    #[serde(rename = "tilewidth")]
+    tile_width: u32,
+    #[serde(rename = "tileheight")]
+    tile_height: u32,
+    layers: Vec<RawLayer>,
+    #[serde(default)]
+    tilesets: Vec<RawTileset>,
+    #[serde(default)]
+    properties: Properties,
+}
+
+/// A tile layer with the Tiled global IDs of its tiles, where 0 means no tile
+pub struct TiledTileLayer<A: Allocator + Clone> {
+    pub name: String,
+    pub properties: Properties,
+    pub gids: ChunkedTileMap<u32, A>,
+}
+
+/// An object placed in an object layer
+#[derive(Debug)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    /// What the object is, which picks how it gets spawned
+    pub class: String,
+    /// The name of the object layer that holds it
+    pub layer: String,
+    /// The object's bounds in tiles, from their top-left corner. Point objects have no
+    /// size
+    pub rect: Rect<f64, TileSpace>,
+    pub properties: Properties,
+}
+
+/// The tile kind names of a tileset
+struct TiledTileset<A: Allocator + Clone> {
+    first_gid: u32,
+    name: String,
+    /// Maps tile IDs within the tileset to tile kind names
+    kinds: HashMap<u32, String, DefaultHashBuilder, A>,
+}
+
+/// A map made with the Tiled editor, read from its JSON format (`.tmj`).
+///
+/// Layers must use CSV encoding and tilesets must be embedded or in `.tsj` files.
+/// Maps saved as `.tmx` or `.tsx` have to be exported as JSON first.
+///
+/// The tiles of a Tiled tileset map to tile kinds through their `kind` property,
+/// then through their class, and otherwise through the name of their tileset.
+pub struct TiledMap<A: Allocator + Clone> {
+    allocator: A,
+
+    pub properties: Properties,
+    /// The tile layers from bottom to top, with groups flattened
+    pub tile_layers: Vec<TiledTileLayer<A>, A>,
+    pub objects: Vec<TiledObject, A>,
+
+    tilesets: Vec<TiledTileset<A>, A>,
+}
+
+/// Just to use Ids without messing with lifetimes
+pub struct TiledMapIdMarker;
+
+impl<A: Allocator + Clone> Resource<'_> for TiledMap<A> {
+    type Id = TiledMapIdMarker;
+}
+
+impl<A: Allocator + Clone> TiledMap<A> {
+    /// Read a map from its JSON form, where `read_tileset` reads the external tilesets
+    /// it refers to by their path relative to the map
+    pub fn from_json_in(
+        allocator: A,
+        json: &str,
+        read_tileset: impl Fn(&str) -> Result<String, ResourceError>,
+    ) -> Result<Self, ResourceError> {
+        let raw: RawMap = serde_json::from_str(json).or(Err(ResourceError::LoadFailed))?;
+
+        let mut map = Self {
+            properties: raw.properties,
+            tile_layers: Vec::new_in(allocator.clone()),
+            objects: Vec::new_in(allocator.clone()),
+            tilesets: Vec::new_in(allocator.clone()),
+            allocator,
+        };
+
+        for raw_tileset in raw.tilesets {
+            let first_gid = raw_tileset.firstgid;
+            let raw_tileset = match &raw_tileset.source {
+                None => raw_tileset,
+                Some(source) if source.ends_with(".tsj") || source.ends_with(".json") => {
+                    serde_json::from_str(&read_tileset(source)?)
+                        .or(Err(ResourceError::LoadFailed))?
+                }
+                Some(_) => {
+                    return Err(ResourceError::Invalid(
+                        "Tiled tilesets must be embedded or saved as JSON",
+                    ));
+                }
+            };
+            map.push_tileset(first_gid, raw_tileset);
+        }
+
+        let tile_size = Size2D::new(raw.tile_width as f64, raw.tile_height as f64);
+        for layer in raw.layers {
+            map.push_layer(layer, tile_size)?;
+        }
+
+        Ok(map)
+    }
+
+    fn push_tileset(&mut self, first_gid: u32, raw: RawTileset) {
+        let mut kinds = HashMap::new_in(self.allocator.clone());
+        for tile in raw.tiles {
+            let kind = match tile.properties.get(PROPERTY_KIND).and_then(|k| k.as_str()) {
+                Some(kind) => kind.to_owned(),
+                None if !tile.class.is_empty() => tile.class,
+                None => continue,
+            };
+            kinds.insert(tile.id, kind);
+        }
+
+        self.tilesets.push(TiledTileset {
+            first_gid,
+            name: raw.name,
+            kinds,
+        });
+    }
+
+    fn push_layer(
+        &mut self,
+        layer: RawLayer,
+        tile_size: Size2D<f64, TileSpace>,
+    ) -> Result<(), ResourceError> {
+        match layer {
+            RawLayer::Tiles {
+                name,
+                width,
+                height,
+                data,
+                chunks,
+                properties,
+            } => {
+                let mut gids = ChunkedTileMap::new_in(self.allocator.clone());
+                Self::read_tiles(&mut gids, TilePoint::origin(), width, height, data)?;
+                for chunk in chunks {
+                    let origin = TilePoint::new(chunk.x, chunk.y);
+                    Self::read_tiles(&mut gids, origin, chunk.width, chunk.height, chunk.data)?;
+                }
+
+                self.tile_layers.push(TiledTileLayer {
+                    name,
+                    properties,
+                    gids,
+                });
+            }
+            RawLayer::Objects { name, objects } => {
+                for object in objects {
+                    let top = match object.gid {
+                        Some(_) => object.y - object.height,
+                        None => object.y,
+                    };
+                    let origin = Point2D::new(object.x / tile_size.width, top / tile_size.height);
+                    let size = Size2D::new(
+                        object.width / tile_size.width,
+                        object.height / tile_size.height,
+                    );
+
+                    self.objects.push(TiledObject {
+                        id: object.id,
+                        name: object.name,
+                        class: object.class,
+                        layer: name.clone(),
+                        rect: Rect::new(origin, size),
+                        properties: object.properties,
+                    });
+                }
+            }
+            RawLayer::Group { layers } => {
+                for layer in layers {
+                    self.push_layer(layer, tile_size)?;
+                }
+            }
+            RawLayer::Image {} => {}
+        }
+
+        Ok(())
+    }
+
+    /// Copy a row-major block of tiles into a layer
+    fn read_tiles(
+        gids: &mut ChunkedTileMap<u32, A>,
+        origin: TilePoint,
+        width: u32,
+        height: u32,
+        data: RawTileData,
+    ) -> Result<(), ResourceError> {
+        let RawTileData::Csv(data) = data else {
+            return Err(ResourceError::Invalid(
+                "Tiled tile layers must use CSV encoding",
+            ));
+        };
+
+        // the sizes come from the file, so they're multiplied without overflowing
+        let cells = width as usize * height as usize;
+
+        // the layers of infinite maps only have tiles in their chunks
+        if data.is_empty() && cells == 0 {
+            return Ok(());
+        }
+        if width == 0 {
+            return Err(ResourceError::Invalid(
+                "Tiled tile layers must have a width",
+            ));
+        }
+        if data.len() != cells {
+            return Err(ResourceError::Invalid(
+                "Tiled tile layers must have a tile for each of their cells",
+            ));
+        }
+
+        for (i, gid) in data.into_iter().enumerate() {
+            if gid == 0 {
+                continue;
+            }
+            let offset = TilePoint::new(i as i32 % width as i32, i as i32 / width as i32);
+            gids.set(origin + offset.to_vector(), gid);
+        }
+        Ok(())
+    }
+
+    /// Get the name of the tile kind of a global tile ID
+    pub fn kind_name(&self, gid: u32) -> Option<&str> {
+        let gid = gid & !GID_FLAGS_MASK;
+        let tileset = self
+            .tilesets
+            .iter()
+            .filter(|t| t.first_gid <= gid)
+            .max_by_key(|t| t.first_gid)?;
+
+        match tileset.kinds.get(&(gid - tileset.first_gid)) {
+            Some(kind) => Some(kind),
+            None => Some(&tileset.name),
+        }
+    }
+
+    /// Convert the tile layers into a level, resolving each tile's kind
+    pub fn to_level_in<LA: Allocator + Clone, KA: Allocator + Clone>(
+        &self,
+        allocator: LA,
+        kinds: &TileKinds<KA>,
+    ) -> Result<Level<LA>, ResourceError> {
+        let mut level = Level::new_in(allocator.clone());
+        let mut resolved: HashMap<u32, TileKindId, DefaultHashBuilder, LA> =
+            HashMap::new_in(allocator.clone());
+
+        for layer in &self.tile_layers {
+            let mut tiles = ChunkedTileMap::new_in(allocator.clone());
+            for (p, gid) in layer.gids.iter().filter(|(_, gid)| **gid != 0) {
+                let kind = match resolved.get(gid) {
+                    Some(kind) => *kind,
+                    None => {
+                        let kind = self
+                            .kind_name(*gid)
+                            .and_then(|name| kinds.find_id(name))
+                            .ok_or(ResourceError::Invalid("Tiled tile has no known tile kind"))?;
+                        resolved.insert(*gid, kind);
+                        kind
+                    }
+                };
+                tiles.set(p, kind);
+            }
+            level.push_layer(&layer.name, tiles);
+        }
+
+        Ok(level)
+    }
+}
+
+/// Loads a `TiledMap` from a `.tmj` file
+pub struct TiledMapLoader<A: Allocator + Clone> {
+    pub(super) root_path: PathBuf,
+
+    allocator: A,
+}
+
+impl<A: Allocator + Clone> TiledMapLoader<A> {
+    pub fn new(allocator: A, root_path: impl Into<PathBuf>) -> Self {
+        Self {
+            allocator,
+            root_path: root_path.into(),
+        }
+    }
+}
+
+impl<'l, 'res, A: Allocator + Clone> ResourceLoader<'l, 'res, TiledMap<A>> for TiledMapLoader<A> {
+    fn load(&'l self, path: &'_ str) -> Result<TiledMap<A>, ResourceError> {
+        let full_path = self.root_path.join(path).with_extension("tmj");
+        let map_dir = full_path.parent().unwrap_or(Path::new(""));
+
+        let json = std::fs::read_to_string(&full_path).or(Err(ResourceError::LoadFailed))?;
+        TiledMap::from_json_in(self.allocator.clone(), &json, |source| {
+            std::fs::read_to_string(map_dir.join(source)).or(Err(ResourceError::LoadFailed))
+        })
+    }
+}
+
+/// A resource manager for `TiledMap`
+pub type TiledMapManager<'res, A> = ResourceManager<'res, 'res, TiledMap<A>, TiledMapLoader<A>, A>;
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    const KINDS: &str = r#"{
+        "type": "tile_kinds",
+        "kinds": [
+            { "id": 1, "name": "floor" },
+            { "id": 2, "name": "wall", "solid": true }
+        ]
+    }"#;
+
+    const MAP: &str = r#"{
+        "type": "map",
+        "tilewidth": 16,
+        "tileheight": 16,
+        "properties": [{ "name": "music", "type": "file", "value": "cave.ogg" }],
+        "tilesets": [
+            { "firstgid": 1, "name": "floor", "tiles": [] },
+            { "firstgid": 5, "source": "walls.tsj" }
+        ],
+        "layers": [
+            {
+                "type": "tilelayer",
+                "name": "ground",
+                "width": 3,
+                "height": 2,
+                "data": [1, 1, 0, 2, 0, 1]
+            },
+            {
+                "type": "group",
+                "layers": [{
+                    "type": "tilelayer",
+                    "name": "walls",
+                    "chunks": [{ "x": -16, "y": 0, "width": 2, "height": 1, "data": [6, 2147483654] }]
+                }]
+            },
+            {
+                "type": "objectgroup",
+                "name": "spawns",
+                "objects": [{
+                    "id": 3,
+                    "name": "first",
+                    "type": "zorb",
+                    "x": 32,
+                    "y": 8,
+                    "properties": [{ "name": "speed", "type": "float", "value": 2.5 }]
+                }, {
+                    "id": 4,
+                    "name": "statue",
+                    "gid": 1,
+                    "x": 16,
+                    "y": 48,
+                    "width": 16,
+                    "height": 32
+                }]
+            }
+        ]
+    }"#;
+
+    const WALLS: &str = r#"{
+        "name": "bricks",
+        "tiles": [{ "id": 1, "properties": [{ "name": "kind", "value": "wall" }] }]
+    }"#;
+
+    fn load() -> TiledMap<Global> {
+        TiledMap::from_json_in(Global, MAP, |source| {
+            assert_eq!(source, "walls.tsj");
+            Ok(WALLS.to_owned())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load_tiled_map() {
+        let map = load();
+
+        assert_eq!(
+            map.properties.get("music"),
+            Some(&PropertyValue::File("cave.ogg".to_owned()))
+        );
+
+        assert_eq!(map.tile_layers.len(), 2);
+        assert_eq!(*map.tile_layers[0].gids.get(TilePoint::new(2, 1)), 1);
+        assert_eq!(
+            *map.tile_layers[1].gids.get(TilePoint::new(-15, 0)),
+            2147483654
+        );
+
+        assert_eq!(map.objects.len(), 2);
+        let zorb = &map.objects[0];
+        assert_eq!(zorb.class, "zorb");
+        assert_eq!(zorb.layer, "spawns");
+        assert_eq!(zorb.rect.origin, Point2D::new(2.0, 0.5));
+        assert_eq!(
+            zorb.properties.get("speed").and_then(|s| s.as_float()),
+            Some(2.5)
+        );
+
+        // tile objects are placed by their bottom-left corner
+        let statue = &map.objects[1];
+        assert_eq!(statue.rect.origin, Point2D::new(1.0, 1.0));
+        assert_eq!(statue.rect.size, Size2D::new(1.0, 2.0));
+    }
+
+    #[test]
+    fn test_tiled_map_to_level() {
+        let map = load();
+        let kinds = TileKinds::from_json_in(Global, KINDS).unwrap();
+
+        let level = map.to_level_in(Global, &kinds).unwrap();
+        let ground = level.layer("ground").unwrap();
+        assert_eq!(*ground.get(TilePoint::new(0, 0)), kinds.get_id("floor"));
+        assert_eq!(*ground.get(TilePoint::new(2, 0)), TileKindId::EMPTY);
+
+        // the flipped tile is the same kind
+        let walls = level.layer("walls").unwrap();
+        assert_eq!(*walls.get(TilePoint::new(-16, 0)), kinds.get_id("wall"));
+        assert_eq!(*walls.get(TilePoint::new(-15, 0)), kinds.get_id("wall"));
+    }
+
+    /// Load a map with a single tile layer
+    fn load_layer(layer: &str) -> Result<TiledMap<Global>, ResourceError> {
+        let json = format!(
+            r#"{{ "type": "map", "tilewidth": 16, "tileheight": 16, "tilesets": [], "layers": [{layer}] }}"#
+        );
+        TiledMap::from_json_in(Global, &json, |_| unreachable!())
+    }
+
+    #[test]
+    fn test_reject_malformed_layers() {
+        let valid =
+            r#"{ "type": "tilelayer", "name": "ground", "width": 2, "height": 1, "data": [1, 0] }"#;
+        assert!(load_layer(valid).is_ok());
+
+        let no_width = r#"{ "type": "tilelayer", "name": "ground", "height": 1, "data": [1, 0] }"#;
+        assert!(matches!(
+            load_layer(no_width),
+            Err(ResourceError::Invalid(_))
+        ));
+
+        let too_short =
+            r#"{ "type": "tilelayer", "name": "ground", "width": 2, "height": 2, "data": [1, 0] }"#;
+        assert!(matches!(
+            load_layer(too_short),
+            Err(ResourceError::Invalid(_))
+        ));
+
+        let huge = r#"{ "type": "tilelayer", "name": "ground", "width": 70000, "height": 70000, "data": [1, 0] }"#;
+        assert!(matches!(load_layer(huge), Err(ResourceError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_reject_unknown_kind() {
+        let map = load();
+        let kinds = TileKinds::from_json_in(
+            Global,
+            r#"{ "type": "tile_kinds", "kinds": [{ "id": 1, "name": "floor" }] }"#,
+        )
+        .unwrap();
+
+        assert!(map.to_level_in(Global, &kinds).is_err());
+    }
+}
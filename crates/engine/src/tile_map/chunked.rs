@@ -0,0 +1,353 @@
+use allocator_api2::alloc::{Allocator, Global as GlobalAllocator};
+use derivative::Derivative;
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::coords::{ChunkPoint, TilePoint};
+
+use super::{NEIGHBORS, NeighborMask, TileMap};
+
+/// The size of a chunk along each axis, in tiles
+pub const CHUNK_SIZE: usize = 32;
+
+/// An unbounded tile map made out of `TileMap` chunks which are only created once
+/// a tile in them gets written to.
+///
+/// Tiles in chunks that don't exist yet read as the default tile.
+#[derive(Derivative)]
+#[derivative(Clone(clone_from = "true"))]
+#[derive(Debug)]
+pub struct ChunkedTileMap<Tile, A: Allocator + Clone = GlobalAllocator> {
+    allocator: A,
+    chunks: HashMap<ChunkPoint, TileMap<Tile, A>, DefaultHashBuilder, A>,
+    /// How many times the tiles bordering each chunk from the chunks around it were
+    /// written to
+    border_revisions: HashMap<ChunkPoint, u64, DefaultHashBuilder, A>,
+    /// Returned when reading from chunks that don't exist
+    empty: Tile,
+}
+
+impl<Tile: Default + Clone, A: Allocator + Clone + Default> Default for ChunkedTileMap<Tile, A> {
+    fn default() -> Self {
+        Self::new_in(A::default())
+    }
+}
+
+/// Split a tile point into its chunk and the position within the chunk
+fn split(p: TilePoint) -> (ChunkPoint, usize, usize) {
+    let size = CHUNK_SIZE as i32;
+    let chunk = ChunkPoint::new(p.x.div_euclid(size), p.y.div_euclid(size));
+    (
+        chunk,
+        p.x.rem_euclid(size) as usize,
+        p.y.rem_euclid(size) as usize,
+    )
+}
+
+impl<Tile: Default + Clone, A: Allocator + Clone> ChunkedTileMap<Tile, A> {
+    pub fn new_in(allocator: A) -> Self {
+        Self {
+            chunks: HashMap::new_in(allocator.clone()),
+            border_revisions: HashMap::new_in(allocator.clone()),
+            allocator,
+            empty: Tile::default(),
+        }
+    }
+
+    /// Get a tile mutable ref, creating its chunk if needed
+    pub fn get_mut(&mut self, p: TilePoint) -> &mut Tile {
+        let (chunk, x, y) = split(p);
+        self.touch_border(chunk, x, y);
+        self.chunks
+            .entry(chunk)
+            .or_insert_with(|| TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, self.allocator.clone()))
+            .get_mut(x, y)
+    }
+
+    /// Set a tile, creating its chunk if needed
+    pub fn set(&mut self, p: TilePoint, tile: Tile) {
+        *self.get_mut(p) = tile;
+    }
+}
+
+impl<Tile, A: Allocator + Clone> ChunkedTileMap<Tile, A> {
+    /// Count a write to a tile of a chunk for the chunks around it that it borders
+    fn touch_border(&mut self, chunk: ChunkPoint, x: usize, y: usize) {
+        let side = |i: usize| match i {
+            0 => -1,
+            i if i == CHUNK_SIZE - 1 => 1,
+            _ => 0,
+        };
+        let (dx, dy) = (side(x), side(y));
+
+        let bordered = [
+            (dx != 0).then_some((dx, 0)),
+            (dy != 0).then_some((0, dy)),
+            (dx != 0 && dy != 0).then_some((dx, dy)),
+        ];
+        for (dx, dy) in bordered.into_iter().flatten() {
+            self.bump_border_revision(ChunkPoint::new(chunk.x + dx, chunk.y + dy));
+        }
+    }
+
+    fn bump_border_revision(&mut self, chunk: ChunkPoint) {
+        *self.border_revisions.entry(chunk).or_insert(0) += 1;
+    }
+
+    /// Get the first tile of a chunk
+    pub fn chunk_origin(chunk: ChunkPoint) -> TilePoint {
+        TilePoint::new(chunk.x * CHUNK_SIZE as i32, chunk.y * CHUNK_SIZE as i32)
+    }
+
+    /// Get the chunk that contains a tile
+    pub fn chunk_of(p: TilePoint) -> ChunkPoint {
+        split(p).0
+    }
+
+    /// Get a tile ref
+    pub fn get(&self, p: TilePoint) -> &Tile {
+        let (chunk, x, y) = split(p);
+        match self.chunks.get(&chunk) {
+            Some(tiles) => tiles.get(x, y),
+            None => &self.empty,
+        }
+    }
+
+    /// Add a whole chunk, replacing the existing one
+    pub fn insert_chunk(&mut self, chunk: ChunkPoint, mut tiles: TileMap<Tile, A>) {
+        assert!(
+            tiles.width() == CHUNK_SIZE && tiles.height() == CHUNK_SIZE,
+            "Chunks must be {CHUNK_SIZE}x{CHUNK_SIZE}"
+        );
+        // the new tiles count as a write, so the chunk never goes back to a revision
+        // it had before
+        if let Some(old) = self.chunks.get(&chunk) {
+            tiles.revision = tiles.revision.max(old.revision) + 1;
+        }
+        tiles.mark_all_changed();
+        self.chunks.insert(chunk, tiles);
+
+        // every tile on the chunk's border changed too
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if dx != 0 || dy != 0 {
+                    self.bump_border_revision(ChunkPoint::new(chunk.x + dx, chunk.y + dy));
+                }
+            }
+        }
+    }
+
+    /// Whether any tile was written to since the changes were last cleared
+    pub fn has_changes(&self) -> bool {
+        self.chunks.values().any(|tiles| tiles.has_changes())
+    }
+
+    /// Iter over the tiles written to since the changes were last cleared, in no
+    /// particular order.
+    ///
+    /// See `TileMap::changes`
+    pub fn changes(&self) -> impl Iterator<Item = TilePoint> {
+        self.chunks().flat_map(|(chunk, tiles)| {
+            let origin = Self::chunk_origin(chunk).to_vector();
+            tiles.changes().map(move |p| p + origin)
+        })
+    }
+
+    /// Forget which tiles changed in every chunk
+    pub fn clear_changes(&mut self) {
+        for tiles in self.chunks.values_mut() {
+            tiles.clear_changes();
+        }
+    }
+
+    /// A number that changes whenever a tile in a chunk or a tile bordering it in the
+    /// chunks around it is written to.
+    ///
+    /// Anything built from a chunk that also looks at its neighbors, like connected
+    /// tiles, only needs to be rebuilt when this changes.
+    pub fn revision_around(&self, chunk: ChunkPoint) -> u64 {
+        // both only go up and chunks are never removed, so neither does the sum
+        let own = self.chunks.get(&chunk).map_or(0, |tiles| tiles.revision());
+        let border = self.border_revisions.get(&chunk).copied().unwrap_or(0);
+        own.wrapping_add(border)
+    }
+
+    /// A number that changes whenever a tile of any chunk may have been written to.
+    ///
+    /// See `TileMap::revision`
+    pub fn revision(&self) -> u64 {
+        // revisions only go up and chunks are never removed, so neither does the sum
+        self.chunks
+            .values()
+            .fold(0u64, |acc, tiles| acc.wrapping_add(tiles.revision()))
+    }
+
+    /// Get a chunk if it was created
+    pub fn chunk(&self, chunk: ChunkPoint) -> Option<&TileMap<Tile, A>> {
+        self.chunks.get(&chunk)
+    }
+
+    /// Iter over all the chunks that were created, in no particular order
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkPoint, &TileMap<Tile, A>)> {
+        self.chunks.iter().map(|(p, tiles)| (*p, tiles))
+    }
+
+    /// Iter over all tiles in the chunks that were created, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (TilePoint, &Tile)> {
+        self.chunks().flat_map(|(chunk, tiles)| {
+            let origin = Self::chunk_origin(chunk);
+            (0..CHUNK_SIZE).flat_map(move |x| {
+                (0..CHUNK_SIZE).map(move |y| {
+                    (
+                        origin + TilePoint::new(x as i32, y as i32).to_vector(),
+                        tiles.get(x, y),
+                    )
+                })
+            })
+        })
+    }
+
+    /// Iter over a tile's neighbors, which may be in other chunks.
+    ///
+    /// See `TileMap::iter_neighbors`
+    pub fn iter_neighbors(&self, p: TilePoint) -> impl Iterator<Item = (NeighborMask, &Tile)> {
+        NEIGHBORS.into_iter().map(move |(np, dx, dy)| {
+            (
+                NeighborMask(np),
+                self.get(TilePoint::new(p.x + dx as i32, p.y + dy as i32)),
+            )
+        })
+    }
+
+    /// Get a mask of neighbors that match the predicate
+    pub fn filter_neighbors<F>(&self, p: TilePoint, predicate: F) -> NeighborMask
+    where
+        F: Fn(&Tile) -> bool,
+    {
+        self.iter_neighbors(p)
+            .filter(|(_np, tile)| predicate(tile))
+            .fold(NeighborMask(0), |acc, (np, _tile)| acc | np)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_map() -> ChunkedTileMap<bool> {
+        ChunkedTileMap::new_in(GlobalAllocator)
+    }
+
+    #[test]
+    fn test_lazy_chunk_creation() {
+        let mut map = new_map();
+        assert!(!*map.get(TilePoint::new(1000, -1000)));
+        assert_eq!(map.chunks().count(), 0);
+
+        map.set(TilePoint::new(1000, -1000), true);
+        assert!(*map.get(TilePoint::new(1000, -1000)));
+        assert_eq!(map.chunks().count(), 1);
+        assert!(
+            map.chunk(ChunkedTileMap::<bool>::chunk_of(TilePoint::new(
+                1000, -1000
+            )))
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn test_negative_coords() {
+        let mut map = new_map();
+        map.set(TilePoint::new(-1, -1), true);
+
+        assert_eq!(
+            ChunkedTileMap::<bool>::chunk_of(TilePoint::new(-1, -1)),
+            ChunkPoint::new(-1, -1)
+        );
+        assert!(*map.get(TilePoint::new(-1, -1)));
+        assert!(!*map.get(TilePoint::new(0, 0)));
+        assert!(!*map.get(TilePoint::new(-1, CHUNK_SIZE as i32 - 1)));
+    }
+
+    #[test]
+    fn test_neighbors_across_chunks() {
+        let mut map = new_map();
+        map.set(TilePoint::new(-1, -1), true);
+        map.set(TilePoint::new(0, -1), true);
+        map.set(TilePoint::new(1, 1), true);
+
+        let mask = map.filter_neighbors(TilePoint::new(0, 0), |t| *t);
+        assert_eq!(
+            mask,
+            NeighborMask(NeighborMask::TOP_LEFT | NeighborMask::TOP | NeighborMask::BOT_RIGHT)
+        );
+    }
+
+    #[test]
+    fn test_revision_around() {
+        let mut map = new_map();
+        let chunk = ChunkPoint::new(0, 0);
+        let start = map.revision_around(chunk);
+
+        // a write in a bordering chunk
+        map.set(TilePoint::new(-1, CHUNK_SIZE as i32), true);
+        let border = map.revision_around(chunk);
+        assert!(border > start);
+
+        // a write two chunks away
+        let total = map.revision();
+        map.set(TilePoint::new(CHUNK_SIZE as i32 * 2, 0), true);
+        assert_eq!(map.revision_around(chunk), border);
+        assert!(map.revision() > total);
+
+        // writes in a neighboring chunk that don't border the chunk
+        map.set(TilePoint::new(-5, CHUNK_SIZE as i32 + 5), true);
+        map.set(TilePoint::new(-(CHUNK_SIZE as i32), 0), true);
+        assert_eq!(map.revision_around(chunk), border);
+
+        // a write on the corner of a diagonal neighbor
+        map.set(TilePoint::new(CHUNK_SIZE as i32, -1), true);
+        let corner = map.revision_around(chunk);
+        assert!(corner > border);
+
+        // replacing a chunk with fresh tiles still counts
+        map.insert_chunk(
+            ChunkPoint::new(-1, 1),
+            TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, GlobalAllocator),
+        );
+        assert!(map.revision_around(chunk) > corner);
+    }
+
+    #[test]
+    fn test_changes_across_chunks() {
+        let mut map = new_map();
+        map.set(TilePoint::new(-1, -1), true);
+        map.set(TilePoint::new(40, 3), true);
+
+        let mut changes: allocator_api2::vec::Vec<_> = map.changes().collect();
+        changes.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(changes[..], [TilePoint::new(-1, -1), TilePoint::new(40, 3)]);
+
+        map.clear_changes();
+        assert!(!map.has_changes());
+
+        // every tile of an inserted chunk is new
+        map.insert_chunk(
+            ChunkPoint::new(5, 5),
+            TileMap::new_in(CHUNK_SIZE, CHUNK_SIZE, GlobalAllocator),
+        );
+        assert_eq!(map.changes().count(), CHUNK_SIZE * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut map = new_map();
+        map.set(TilePoint::new(-5, 3), true);
+        map.set(TilePoint::new(40, 3), true);
+
+        let mut set: allocator_api2::vec::Vec<_> =
+            map.iter().filter(|(_, t)| **t).map(|(p, _)| p).collect();
+        set.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(set[..], [TilePoint::new(-5, 3), TilePoint::new(40, 3)]);
+        assert_eq!(map.iter().count(), 2 * CHUNK_SIZE * CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,246 @@
+use allocator_api2::alloc::Allocator;
+use euclid::{Box2D, Vector2D};
+
+use crate::coords::{TilePoint, TileSpace, TileVector};
+
+use super::TileMap;
+
+/// How far inside a tile a box edge has to be to count as overlapping it, so a box
+/// that was stopped right against a tile doesn't get stuck in it from rounding errors
+const EPSILON: f64 = 1e-9;
+
+/// A solid tile that stopped a moving box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileContact {
+    pub tile: TilePoint,
+    /// The side of the tile that was hit, pointing out of the tile
+    pub normal: TileVector,
+}
+
+/// The tiles that stopped a moving box along each axis
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Contacts {
+    pub x: Option<TileContact>,
+    pub y: Option<TileContact>,
+}
+
+impl Contacts {
+    pub fn is_empty(&self) -> bool {
+        self.x.is_none() && self.y.is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TileContact> {
+        self.x.into_iter().chain(self.y)
+    }
+}
+
+/// How a box moved through tiles
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoxMove {
+    /// How far the box actually moved, which is shorter than asked along the axes it
+    /// hit something
+    pub motion: Vector2D<f64, TileSpace>,
+    pub contacts: Contacts,
+}
+
+#[derive(Copy, Clone)]
+enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    /// The tile at a position along this axis and across it
+    fn tile(self, along: i32, across: i32) -> TilePoint {
+        match self {
+            Self::X => TilePoint::new(along, across),
+            Self::Y => TilePoint::new(across, along),
+        }
+    }
+
+    /// The unit vector along this axis
+    fn unit(self, sign: i32) -> TileVector {
+        self.tile(sign, 0).to_vector()
+    }
+
+    /// The extent of a box along this axis and across it
+    fn split(self, bounds: &Box2D<f64, TileSpace>) -> ((f64, f64), (f64, f64)) {
+        match self {
+            Self::X => ((bounds.min.x, bounds.max.x), (bounds.min.y, bounds.max.y)),
+            Self::Y => ((bounds.min.y, bounds.max.y), (bounds.min.x, bounds.max.x)),
+        }
+    }
+}
+
+/// Move a box along one axis until it hits a solid tile in any of the rows or
+/// columns it overlaps
+fn sweep_axis<F>(
+    bounds: &Box2D<f64, TileSpace>,
+    motion: f64,
+    axis: Axis,
+    is_solid: &F,
+) -> (f64, Option<TileContact>)
+where
+    F: Fn(TilePoint) -> bool,
+{
+    let ((min, max), (across_min, across_max)) = axis.split(bounds);
+    let across = (across_min + EPSILON).floor() as i32..(across_max - EPSILON).ceil() as i32;
+
+    let hit = |along: i32, sign: i32| {
+        across
+            .clone()
+            .map(|a| axis.tile(along, a))
+            .find(|p| is_solid(*p))
+            .map(|tile| TileContact {
+                tile,
+                normal: axis.unit(-sign),
+            })
+    };
+
+    if motion > 0.0 {
+        // the tiles entered by the leading edge, nearest first
+        let first = (max - EPSILON).ceil() as i32;
+        let last = (max + motion - EPSILON).ceil() as i32;
+        for along in first..last {
+            if let Some(contact) = hit(along, 1) {
+                return ((along as f64 - max).max(0.0), Some(contact));
+            }
+        }
+    } else if motion < 0.0 {
+        let first = (min + EPSILON).floor() as i32 - 1;
+        let last = (min + motion + EPSILON).floor() as i32;
+        for along in (last..=first).rev() {
+            if let Some(contact) = hit(along, -1) {
+                return ((along as f64 + 1.0 - min).min(0.0), Some(contact));
+            }
+        }
+    }
+
+    (motion, None)
+}
+
+/// Move a box through tiles, stopping against solid tiles without skipping any of
+/// them however far it moves.
+///
+/// The box moves horizontally then vertically, so it keeps sliding along a wall it
+/// hits at an angle. Tiles it already overlaps don't stop it, so it can get out of a
+/// tile that became solid under it.
+pub fn move_box<F>(
+    bounds: Box2D<f64, TileSpace>,
+    motion: Vector2D<f64, TileSpace>,
+    is_solid: F,
+) -> BoxMove
+where
+    F: Fn(TilePoint) -> bool,
+{
+    let (x, contact_x) = sweep_axis(&bounds, motion.x, Axis::X, &is_solid);
+    let moved = bounds.translate(Vector2D::new(x, 0.0));
+    let (y, contact_y) = sweep_axis(&moved, motion.y, Axis::Y, &is_solid);
+
+    BoxMove {
+        motion: Vector2D::new(x, y),
+        contacts: Contacts {
+            x: contact_x,
+            y: contact_y,
+        },
+    }
+}
+
+impl<Tile, A: Allocator + Clone> TileMap<Tile, A> {
+    /// Move a box within the map, where tiles out of the map are never solid.
+    ///
+    /// See `move_box`
+    pub fn move_box<F>(
+        &self,
+        bounds: Box2D<f64, TileSpace>,
+        motion: Vector2D<f64, TileSpace>,
+        is_solid: F,
+    ) -> BoxMove
+    where
+        F: Fn(&Tile) -> bool,
+    {
+        move_box(bounds, motion, |p| self.try_get(p).is_some_and(&is_solid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::Point2D;
+
+    use super::*;
+    use crate::tile_map::rows;
+
+    /// A box of a size centered on a point
+    fn centered(x: f64, y: f64, size: f64) -> Box2D<f64, TileSpace> {
+        let half = Vector2D::splat(size / 2.0);
+        let center = Point2D::new(x, y);
+        Box2D::new(center - half, center + half)
+    }
+
+    #[test]
+    fn test_stop_against_wall() {
+        let map = TileMap::from_rows(&["...#"], rows::solid);
+        let moved = map.move_box(centered(0.5, 0.5, 0.5), Vector2D::new(5.0, 0.0), |t| *t);
+
+        // the right edge stops at the wall's left side
+        assert_eq!(moved.motion, Vector2D::new(2.25, 0.0));
+        let contact = moved.contacts.x.unwrap();
+        assert_eq!(contact.tile, TilePoint::new(3, 0));
+        assert_eq!(contact.normal, TileVector::new(-1, 0));
+        assert_eq!(moved.contacts.y, None);
+
+        // pushing again doesn't move it any further or into the wall
+        let against = centered(0.5 + 2.25, 0.5, 0.5);
+        let moved = map.move_box(against, Vector2D::new(0.1, 0.0), |t| *t);
+        assert_eq!(moved.motion.x, 0.0);
+        assert!(!moved.contacts.is_empty());
+
+        // and it can leave
+        let moved = map.move_box(against, Vector2D::new(-1.0, 0.0), |t| *t);
+        assert_eq!(moved.motion.x, -1.0);
+        assert!(moved.contacts.is_empty());
+    }
+
+    #[test]
+    fn test_slide_along_wall() {
+        let map = TileMap::from_rows(
+            &[
+                "....", //
+                "....", //
+                "####",
+            ],
+            rows::solid,
+        );
+        let moved = map.move_box(centered(0.5, 1.5, 0.5), Vector2D::new(2.0, 2.0), |t| *t);
+
+        assert_eq!(moved.motion, Vector2D::new(2.0, 0.25));
+        assert_eq!(moved.contacts.x, None);
+        assert_eq!(moved.contacts.y.unwrap().normal, TileVector::new(0, -1));
+        assert_eq!(moved.contacts.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_no_tunneling() {
+        let map = TileMap::from_rows(&[".#......"], rows::solid);
+        let moved = map.move_box(centered(7.5, 0.5, 0.8), Vector2D::new(-100.0, 0.0), |t| *t);
+        assert!((moved.motion.x + 5.1).abs() < 1e-9, "{:?}", moved.motion);
+        assert_eq!(moved.contacts.x.unwrap().tile, TilePoint::new(1, 0));
+        assert_eq!(moved.contacts.x.unwrap().normal, TileVector::new(1, 0));
+    }
+
+    #[test]
+    fn test_boxes_wider_than_a_tile() {
+        let map = TileMap::from_rows(
+            &[
+                "...", //
+                "...", //
+                "..#",
+            ],
+            rows::solid,
+        );
+        // only the right part of the box goes over the wall
+        let moved = map.move_box(centered(1.5, 0.5, 1.5), Vector2D::new(0.0, 2.0), |t| *t);
+        assert_eq!(moved.motion.y, 0.75);
+        assert_eq!(moved.contacts.y.unwrap().tile, TilePoint::new(2, 2));
+    }
+}
@@ -0,0 +1,376 @@
+use std::f32::consts::SQRT_2;
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::coords::{TilePoint, TileVector};
+
+use super::{NEIGHBORS, TileMap, open_list::OpenList, path::DiagonalMoves};
+
+#[derive(Copy, Clone, Debug)]
+struct FlowTile {
+    /// The cost of the cheapest path to the goal
+    distance: f32,
+    /// The next tile on that path
+    next: TilePoint,
+}
+
+/// A Dijkstra map: the cheapest path from every reachable tile to one goal.
+///
+/// Any number of agents can share it, each only looking up the next tile from where it
+/// stands. When tiles change, `update` only recomputes the tiles whose paths went
+/// through them instead of rebuilding the whole field.
+pub struct FlowField<A: Allocator + Clone> {
+    pub diagonals: DiagonalMoves,
+    /// Tiles further than this from the goal are left out of the field
+    pub max_distance: f32,
+
+    goal: Option<TilePoint>,
+    tiles: HashMap<TilePoint, FlowTile, DefaultHashBuilder, A>,
+    /// Tiles to expand, ordered by their distance
+    open: OpenList<A>,
+    /// Tiles that lost their distance during an update
+    stale: Vec<TilePoint, A>,
+}
+
+impl<A: Allocator + Clone> FlowField<A> {
+    pub fn new_in(allocator: A, diagonals: DiagonalMoves, max_distance: f32) -> Self {
+        Self {
+            diagonals,
+            max_distance,
+            goal: None,
+            tiles: HashMap::new_in(allocator.clone()),
+            open: OpenList::new_in(allocator.clone()),
+            stale: Vec::new_in(allocator),
+        }
+    }
+
+    /// The goal of the last build, if any
+    pub fn goal(&self) -> Option<TilePoint> {
+        self.goal
+    }
+
+    /// The number of tiles that can reach the goal
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// The cost of the cheapest path from a tile to the goal, or `None` if it can't
+    /// reach it
+    pub fn distance(&self, p: TilePoint) -> Option<f32> {
+        self.tiles.get(&p).map(|t| t.distance)
+    }
+
+    /// The tile to step onto from `p` to get closer to the goal. The goal leads to itself
+    pub fn next_tile(&self, p: TilePoint) -> Option<TilePoint> {
+        self.tiles.get(&p).map(|t| t.next)
+    }
+
+    /// The step to take from `p` to get closer to the goal
+    pub fn direction(&self, p: TilePoint) -> Option<TileVector> {
+        self.next_tile(p).map(|next| next - p)
+    }
+
+    /// Compute the whole field towards `goal`.
+    ///
+    /// `cost` is the cost of stepping onto a tile or `None` if it's impassable, like
+    /// for `Pathfinder::find_path`, so distances match the cost of the paths it finds.
+    pub fn build<C>(&mut self, goal: TilePoint, cost: C)
+    where
+        C: Fn(TilePoint) -> Option<f32>,
+    {
+        self.goal = Some(goal);
+        self.tiles.clear();
+        self.open.clear();
+
+        if cost(goal).is_none() {
+            return;
+        }
+
+        self.tiles.insert(
+            goal,
+            FlowTile {
+                distance: 0.0,
+                next: goal,
+            },
+        );
+        self.open.push(0.0, goal);
+        self.expand(&cost);
+    }
+
+    /// Recompute the field after the cost of some tiles changed.
+    ///
+    /// Only the tiles whose paths went through changed tiles are recomputed, and tiles
+    /// around them get a chance to find cheaper paths. `cost` must already return the
+    /// new costs.
+    pub fn update<I, C>(&mut self, changed: I, cost: C)
+    where
+        I: IntoIterator<Item = TilePoint>,
+        C: Fn(TilePoint) -> Option<f32>,
+    {
+        let Some(goal) = self.goal else {
+            return;
+        };
+
+        self.stale.clear();
+        self.open.clear();
+
+        for p in changed {
+            if p == goal {
+                self.build(goal, cost);
+                return;
+            }
+
+            self.tiles.remove(&p);
+            self.stale.push(p);
+
+            // diagonal moves around this tile may not be allowed anymore
+            for (_, dx, dy) in NEIGHBORS {
+                let np = p + TileVector::new(dx as i32, dy as i32);
+                let Some(tile) = self.tiles.get(&np) else {
+                    continue;
+                };
+                let step = tile.next - np;
+                if step.x != 0
+                    && step.y != 0
+                    && (np + TileVector::new(step.x, 0) == p
+                        || np + TileVector::new(0, step.y) == p)
+                {
+                    self.tiles.remove(&np);
+                    self.stale.push(np);
+                }
+            }
+        }
+
+        // forget every tile whose path went through a stale tile
+        let mut i = 0;
+        while i < self.stale.len() {
+            let p = self.stale[i];
+            for (_, dx, dy) in NEIGHBORS {
+                let np = p + TileVector::new(dx as i32, dy as i32);
+                if self.tiles.get(&np).is_some_and(|t| t.next == p) {
+                    self.tiles.remove(&np);
+                    self.stale.push(np);
+                }
+            }
+            i += 1;
+        }
+
+        // expand again from the tiles that still know their distance around the stale
+        // ones, which also lets cheaper paths through changed tiles spread
+        for &p in &self.stale {
+            for (_, dx, dy) in NEIGHBORS {
+                let np = p + TileVector::new(dx as i32, dy as i32);
+                if let Some(tile) = self.tiles.get(&np) {
+                    self.open.push(tile.distance, np);
+                }
+            }
+        }
+
+        self.expand(&cost);
+    }
+
+    /// Spread distances from the open tiles until none can get cheaper
+    fn expand<C>(&mut self, cost: &C)
+    where
+        C: Fn(TilePoint) -> Option<f32>,
+    {
+        while let Some(open) = self.open.pop() {
+            let p = open.p;
+            let Some(tile) = self.tiles.get(&p).copied() else {
+                continue;
+            };
+            if open.cost > tile.distance {
+                continue;
+            }
+            // `p` was found passable when it was reached, but may have been updated since
+            let Some(cost_p) = cost(p) else {
+                continue;
+            };
+
+            for (_, dx, dy) in NEIGHBORS {
+                let np = p + TileVector::new(dx as i32, dy as i32);
+                if cost(np).is_none() {
+                    continue;
+                }
+
+                let step_cost = if dx != 0 && dy != 0 {
+                    let open_x = cost(p + TileVector::new(dx as i32, 0)).is_some();
+                    let open_y = cost(p + TileVector::new(0, dy as i32)).is_some();
+                    if !self.diagonals.allows(open_x, open_y) {
+                        continue;
+                    }
+                    cost_p * SQRT_2
+                } else {
+                    cost_p
+                };
+
+                let distance = tile.distance + step_cost;
+                if distance > self.max_distance
+                    || self.tiles.get(&np).is_some_and(|t| t.distance <= distance)
+                {
+                    continue;
+                }
+
+                self.tiles.insert(np, FlowTile { distance, next: p });
+                self.open.push(distance, np);
+            }
+        }
+    }
+
+    /// Compute the field within a tile map, where tiles out of the map are impassable.
+    ///
+    /// See `build`
+    pub fn build_in<Tile, MA, C>(&mut self, map: &TileMap<Tile, MA>, goal: TilePoint, cost: C)
+    where
+        MA: Allocator + Clone,
+        C: Fn(&Tile) -> Option<f32>,
+    {
+        self.build(goal, |p| map.try_get(p).and_then(&cost));
+    }
+
+    /// Recompute the field within a tile map after some of its tiles changed.
+    ///
+    /// See `update`
+    pub fn update_in<Tile, MA, I, C>(&mut self, map: &TileMap<Tile, MA>, changed: I, cost: C)
+    where
+        MA: Allocator + Clone,
+        I: IntoIterator<Item = TilePoint>,
+        C: Fn(&Tile) -> Option<f32>,
+    {
+        self.update(changed, |p| map.try_get(p).and_then(&cost));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+    use crate::tile_map::rows;
+
+    fn field(map: &TileMap<Option<f32>, Global>, goal: (i32, i32)) -> FlowField<Global> {
+        let mut field = FlowField::new_in(Global, DiagonalMoves::NoCornerCutting, f32::MAX);
+        field.build_in(map, TilePoint::new(goal.0, goal.1), |c| *c);
+        field
+    }
+
+    fn assert_same_distances(
+        map: &TileMap<Option<f32>, Global>,
+        a: &FlowField<Global>,
+        b: &FlowField<Global>,
+    ) {
+        assert_eq!(a.len(), b.len());
+        for y in 0..map.height() as i32 {
+            for x in 0..map.width() as i32 {
+                let p = TilePoint::new(x, y);
+                match (a.distance(p), b.distance(p)) {
+                    (Some(a), Some(b)) => assert!((a - b).abs() < 1e-4, "{p:?}: {a} != {b}"),
+                    (a, b) => assert_eq!(a, b, "{p:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_follow_field_to_goal() {
+        let map = TileMap::from_rows(
+            &[
+                ".....", //
+                ".###.", //
+                ".....",
+            ],
+            rows::cost,
+        );
+        let field = field(&map, (2, 2));
+
+        let mut p = TilePoint::new(2, 0);
+        let mut steps = 0;
+        while p != TilePoint::new(2, 2) {
+            p = field.next_tile(p).unwrap();
+            assert_ne!(map.try_get(p), Some(&None));
+            steps += 1;
+        }
+        assert_eq!(steps, 6);
+        assert_eq!(
+            field.direction(TilePoint::new(2, 2)),
+            Some(TileVector::zero())
+        );
+        assert_eq!(field.distance(TilePoint::new(1, 2)), Some(1.0));
+    }
+
+    #[test]
+    fn test_unreachable_tiles() {
+        let map = TileMap::from_rows(&[".#.", "##."], rows::cost);
+        let field = field(&map, (2, 0));
+        assert_eq!(field.distance(TilePoint::new(0, 0)), None);
+        assert_eq!(field.distance(TilePoint::new(1, 0)), None);
+        assert_eq!(field.len(), 2);
+
+        let field = self::field(&map, (1, 0));
+        assert!(field.is_empty());
+    }
+
+    #[test]
+    fn test_max_distance() {
+        let map = TileMap::from_rows(&["......"], rows::cost);
+        let mut field = FlowField::new_in(Global, DiagonalMoves::Never, 2.0);
+        field.build_in(&map, TilePoint::new(0, 0), |c| *c);
+        assert_eq!(field.len(), 3);
+    }
+
+    #[test]
+    fn test_update_matches_rebuild() {
+        let mut map = TileMap::from_rows(
+            &[
+                "........", //
+                "..#.....", //
+                "..#..~..", //
+                "..#.....", //
+                "........",
+            ],
+            rows::cost,
+        );
+        let goal = (0, 2);
+        let mut updated = field(&map, goal);
+
+        let changes: &[((usize, usize), Option<f32>)] = &[
+            // wall off the bottom, then the top
+            ((2, 4), None),
+            ((2, 0), None),
+            // open a hole in the middle of the wall
+            ((2, 2), Some(1.0)),
+            // make the hole expensive
+            ((2, 2), Some(5.0)),
+            // block a diagonal corner and free the mud
+            ((4, 1), None),
+            ((5, 2), Some(1.0)),
+            // close everything again
+            ((2, 2), None),
+        ];
+
+        for &((x, y), cost) in changes {
+            map.set(x, y, cost);
+            updated.update_in(&map, [TilePoint::new(x as i32, y as i32)], |c| *c);
+            assert_same_distances(&map, &updated, &field(&map, goal));
+        }
+    }
+
+    #[test]
+    fn test_update_goal() {
+        let mut map = TileMap::from_rows(&["...."], rows::cost);
+        let mut field = field(&map, (0, 0));
+
+        map.set(0, 0, None);
+        field.update_in(&map, [TilePoint::new(0, 0)], |c| *c);
+        assert!(field.is_empty());
+
+        map.set(0, 0, Some(1.0));
+        field.update_in(&map, [TilePoint::new(0, 0)], |c| *c);
+        assert_eq!(field.distance(TilePoint::new(3, 0)), Some(3.0));
+    }
+}
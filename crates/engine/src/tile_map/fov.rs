@@ -0,0 +1,219 @@
+use allocator_api2::alloc::Allocator;
+
+use crate::coords::{TilePoint, TileVector};
+
+use super::TileMap;
+
+/// How to map the first octant onto each of the eight octants around the origin, as
+/// `(xx, xy, yx, yy)`
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+/// The tiles visible from one point within a radius, found with recursive shadowcasting.
+///
+/// The visibility mask is a square tile map centered on the origin, which is reused
+/// between computations.
+pub struct FieldOfView<A: Allocator + Clone> {
+    origin: TilePoint,
+    radius: i32,
+    visible: TileMap<bool, A>,
+}
+
+impl<A: Allocator + Clone> FieldOfView<A> {
+    pub fn new_in(allocator: A, radius: usize) -> Self {
+        let size = radius * 2 + 1;
+        Self {
+            origin: TilePoint::origin(),
+            radius: radius as i32,
+            visible: TileMap::new_in(size, size, allocator),
+        }
+    }
+
+    pub fn origin(&self) -> TilePoint {
+        self.origin
+    }
+
+    pub fn radius(&self) -> usize {
+        self.radius as usize
+    }
+
+    /// Convert a tile to a position in the visibility mask
+    fn to_mask(&self, p: TilePoint) -> TilePoint {
+        p - self.origin.to_vector() + TileVector::splat(self.radius)
+    }
+
+    /// Whether a tile was visible from the origin at the last computation
+    pub fn is_visible(&self, p: TilePoint) -> bool {
+        self.visible
+            .try_get(self.to_mask(p))
+            .is_some_and(|visible| *visible)
+    }
+
+    /// Iterate over all the tiles that were visible at the last computation
+    pub fn iter_visible(&self) -> impl Iterator<Item = TilePoint> {
+        let size = self.radius * 2 + 1;
+        let offset = self.origin.to_vector() - TileVector::splat(self.radius);
+        (0..size)
+            .flat_map(move |y| (0..size).map(move |x| TilePoint::new(x, y)))
+            .filter(|p| *self.visible.get(p.x as usize, p.y as usize))
+            .map(move |p| p + offset)
+    }
+
+    /// Find the tiles visible from `origin`. Tiles that block light are visible
+    /// themselves, but hide what's behind them
+    pub fn compute<F>(&mut self, origin: TilePoint, blocks_light: F)
+    where
+        F: Fn(TilePoint) -> bool,
+    {
+        self.origin = origin;
+        self.visible.clear();
+        self.mark_visible(origin);
+
+        for octant in OCTANTS {
+            self.cast_light(&blocks_light, octant, 1, 1.0, 0.0);
+        }
+    }
+
+    /// Find the tiles visible within a tile map, where tiles out of the map never block
+    /// light.
+    ///
+    /// See `compute`
+    pub fn compute_in<Tile, MA, F>(
+        &mut self,
+        map: &TileMap<Tile, MA>,
+        origin: TilePoint,
+        blocks_light: F,
+    ) where
+        MA: Allocator + Clone,
+        F: Fn(&Tile) -> bool,
+    {
+        self.compute(origin, |p| map.try_get(p).is_some_and(&blocks_light));
+    }
+
+    fn mark_visible(&mut self, p: TilePoint) {
+        let p = self.to_mask(p);
+        self.visible.try_set(p, true);
+    }
+
+    /// Scan the rows of an octant from `row` outwards, between the `start` and `end`
+    /// slopes, recursing into the gaps between blocking tiles
+    fn cast_light<F>(
+        &mut self,
+        blocks_light: &F,
+        (xx, xy, yx, yy): (i32, i32, i32, i32),
+        row: i32,
+        mut start: f64,
+        end: f64,
+    ) where
+        F: Fn(TilePoint) -> bool,
+    {
+        if start < end {
+            return;
+        }
+
+        let mut next_start = start;
+        for distance in row..=self.radius {
+            let dy = -distance;
+            let mut blocked = false;
+
+            for dx in -distance..=0 {
+                // the slopes of the tile's left and right corners
+                let left = (dx as f64 - 0.5) / (dy as f64 + 0.5);
+                let right = (dx as f64 + 0.5) / (dy as f64 - 0.5);
+                if start < right {
+                    continue;
+                }
+                if end > left {
+                    break;
+                }
+
+                let p = self.origin + TileVector::new(dx * xx + dy * xy, dx * yx + dy * yy);
+                if dx * dx + dy * dy <= self.radius * self.radius {
+                    self.mark_visible(p);
+                }
+
+                if blocked {
+                    if blocks_light(p) {
+                        next_start = right;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if blocks_light(p) && distance < self.radius {
+                    blocked = true;
+                    self.cast_light(blocks_light, (xx, xy, yx, yy), distance + 1, start, left);
+                    next_start = right;
+                }
+            }
+
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+    use crate::tile_map::rows;
+
+    #[test]
+    fn test_open_area() {
+        let map = TileMap::from_rows(&[".....", ".....", ".....", ".....", "....."], rows::solid);
+        let mut fov = FieldOfView::new_in(Global, 2);
+        fov.compute_in(&map, TilePoint::new(2, 2), |t| *t);
+
+        // everything within the radius, which cuts the corners
+        assert_eq!(fov.iter_visible().count(), 13);
+        assert!(fov.is_visible(TilePoint::new(2, 0)));
+        assert!(fov.is_visible(TilePoint::new(3, 3)));
+        assert!(!fov.is_visible(TilePoint::new(0, 0)));
+        assert!(!fov.is_visible(TilePoint::new(9, 9)));
+    }
+
+    #[test]
+    fn test_walls_cast_shadows() {
+        let map = TileMap::from_rows(
+            &[
+                ".......", //
+                "...#...", //
+                ".......", //
+                "...@...",
+            ],
+            rows::solid,
+        );
+        let mut fov = FieldOfView::new_in(Global, 5);
+        fov.compute_in(&map, TilePoint::new(3, 3), |t| *t);
+
+        assert!(fov.is_visible(TilePoint::new(3, 1)));
+        assert!(!fov.is_visible(TilePoint::new(3, 0)));
+        assert!(fov.is_visible(TilePoint::new(0, 0)));
+        assert!(fov.is_visible(TilePoint::new(6, 0)));
+    }
+
+    #[test]
+    fn test_recompute_moves_origin() {
+        let map = TileMap::from_rows(&["...#..."], rows::solid);
+        let mut fov = FieldOfView::new_in(Global, 6);
+
+        fov.compute_in(&map, TilePoint::new(0, 0), |t| *t);
+        assert!(fov.is_visible(TilePoint::new(3, 0)));
+        assert!(!fov.is_visible(TilePoint::new(5, 0)));
+
+        fov.compute_in(&map, TilePoint::new(6, 0), |t| *t);
+        assert_eq!(fov.origin(), TilePoint::new(6, 0));
+        assert!(fov.is_visible(TilePoint::new(4, 0)));
+        assert!(!fov.is_visible(TilePoint::new(1, 0)));
+    }
+}
@@ -0,0 +1,346 @@
+use allocator_api2::vec::Vec;
+use sdl3::render::FRect;
+use serde::{Deserialize, Serialize};
+
+use crate::coords::TilePoint;
+
+use super::{NEIGHBORS, NeighborMask, mask::BLOB_MASKS};
+
+/// A tile of a custom layout and the neighbors it connects to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CustomTile {
+    pub mask: u8,
+    pub x: u8,
+    pub y: u8,
+}
+
+/// How the tiles of a tileset are arranged in its cel, which decides which tile is
+/// drawn for each combination of connected neighbors
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TileLayout {
+    /// The 47 tiles that cover every combination of edges and inner corners, in a 12x4
+    /// grid
+    #[default]
+    Blob47,
+    /// 16 tiles that only connect up, down, left and right, in a 4x4 grid indexed by
+    /// `top | right << 1 | bottom << 2 | left << 3`
+    Edges16,
+    /// 16 tiles drawn between tiles rather than on them, in a 4x4 grid indexed by which
+    /// of the four tiles around them are set:
+    /// `top_left | top_right << 1 | bottom_left << 2 | bottom_right << 3`
+    ///
+    /// Each map tile is drawn as the four quarters of the tiles on its corners.
+    DualGrid,
+    /// Any grid with an explicit table of masks.
+    ///
+    /// Masks that aren't in the table are looked up again without their diagonals that
+    /// can't connect, then without any diagonals, and fall back to the first tile.
+    Custom {
+        width: u8,
+        height: u8,
+        tiles: Vec<CustomTile>,
+    },
+}
+
+/// A piece of a tileset tile to draw over a piece of a map tile
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TilePart {
+    /// The tile in the tileset's grid
+    pub tile: (u8, u8),
+    /// Where the piece is within the tileset tile, in fractions of a tile
+    pub src: FRect,
+    /// Where the piece goes within the map tile, in fractions of a tile
+    pub dst: FRect,
+}
+
+impl TilePart {
+    const WHOLE: FRect = FRect {
+        x: 0.0,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+    };
+
+    fn whole(tile: (u8, u8)) -> Self {
+        Self {
+            tile,
+            src: Self::WHOLE,
+            dst: Self::WHOLE,
+        }
+    }
+}
+
+/// Remove the diagonal neighbors that aren't next to both of their edge neighbors, as
+/// they can't connect visually
+fn without_loose_corners(mask: u8) -> u8 {
+    let has = |bits: u8| mask & bits == bits;
+    let mut filtered =
+        mask & (NeighborMask::TOP | NeighborMask::BOT | NeighborMask::LEFT | NeighborMask::RIGHT);
+
+    for (corner, edges) in [
+        (
+            NeighborMask::TOP_LEFT,
+            NeighborMask::TOP | NeighborMask::LEFT,
+        ),
+        (
+            NeighborMask::TOP_RIGHT,
+            NeighborMask::TOP | NeighborMask::RIGHT,
+        ),
+        (
+            NeighborMask::BOT_LEFT,
+            NeighborMask::BOT | NeighborMask::LEFT,
+        ),
+        (
+            NeighborMask::BOT_RIGHT,
+            NeighborMask::BOT | NeighborMask::RIGHT,
+        ),
+    ] {
+        if has(corner | edges) {
+            filtered |= corner;
+        }
+    }
+
+    filtered
+}
+
+/// Whether the neighbor at an offset is in a mask, where the tile itself always is
+fn has_neighbor(mask: NeighborMask, dx: i16, dy: i16) -> bool {
+    (dx == 0 && dy == 0)
+        || NEIGHBORS
+            .iter()
+            .any(|(bit, ndx, ndy)| *ndx == dx && *ndy == dy && mask.0 & bit != 0)
+}
+
+/// A well mixed hash of a tile's position, so tiles can look different from their
+/// neighbors without storing anything, and look the same on every frame and run
+pub fn tile_hash(p: TilePoint) -> u32 {
+    let mut h = (p.x as u32).wrapping_mul(0x9e37_79b9) ^ (p.y as u32).wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h
+}
+
+/// Pick the index of a variant from a hash, where each variant is picked in proportion
+/// to its weight. No weights means a single variant
+pub fn pick_variant(weights: &[u16], hash: u32) -> usize {
+    let total: u32 = weights.iter().map(|w| *w as u32).sum();
+    if total == 0 {
+        return 0;
+    }
+
+    let mut roll = hash % total;
+    for (i, weight) in weights.iter().enumerate() {
+        if roll < *weight as u32 {
+            return i;
+        }
+        roll -= *weight as u32;
+    }
+    unreachable!("The roll is below the total weight")
+}
+
+impl TileLayout {
+    /// The size of the grid of tiles in the cel, in tiles
+    pub fn size(&self) -> (u8, u8) {
+        match self {
+            Self::Blob47 => (12, 4),
+            Self::Edges16 | Self::DualGrid => (4, 4),
+            Self::Custom { width, height, .. } => (*width, *height),
+        }
+    }
+
+    /// Check that a custom layout only points to tiles within its grid
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Self::Custom {
+            width,
+            height,
+            tiles,
+        } = self
+        {
+            if tiles.is_empty() {
+                return Err("custom tile layouts need at least one tile");
+            }
+            if tiles.iter().any(|t| t.x >= *width || t.y >= *height) {
+                return Err("custom tile layout has tiles out of its grid");
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the position of the tile to draw in the grid. Dual grids don't have one
+    /// tile per mask, see `parts`
+    fn tile_for(&self, mask: NeighborMask) -> (u8, u8) {
+        match self {
+            Self::Blob47 => BLOB_MASKS[mask.0 as usize],
+            Self::DualGrid => unreachable!("Dual grid tiles are drawn in quarters"),
+            Self::Edges16 => {
+                let index = [
+                    NeighborMask::TOP,
+                    NeighborMask::RIGHT,
+                    NeighborMask::BOT,
+                    NeighborMask::LEFT,
+                ]
+                .into_iter()
+                .enumerate()
+                .fold(0, |acc, (i, bit)| acc | ((mask.0 & bit != 0) as u8) << i);
+                (index % 4, index / 4)
+            }
+            Self::Custom { tiles, .. } => {
+                let edges = NeighborMask::TOP
+                    | NeighborMask::BOT
+                    | NeighborMask::LEFT
+                    | NeighborMask::RIGHT;
+                [mask.0, without_loose_corners(mask.0), mask.0 & edges]
+                    .into_iter()
+                    .find_map(|m| tiles.iter().find(|t| t.mask == m))
+                    .or(tiles.first())
+                    .map_or((0, 0), |t| (t.x, t.y))
+            }
+        }
+    }
+
+    /// Get the pieces to draw for a tile according to its connected neighbors
+    pub fn parts(&self, mask: NeighborMask) -> impl Iterator<Item = TilePart> {
+        let (whole, quarters) = match self {
+            Self::DualGrid => (None, Some(Self::dual_grid_quarters(mask))),
+            _ => (Some(TilePart::whole(self.tile_for(mask))), None),
+        };
+        whole.into_iter().chain(quarters.into_iter().flatten())
+    }
+
+    /// Each quarter of a map tile is covered by the quarter of the dual tile on that
+    /// corner which overlaps it
+    fn dual_grid_quarters(mask: NeighborMask) -> [TilePart; 4] {
+        [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(cx, cy): (i16, i16)| {
+            // the four map tiles around the corner, where this one is opposite it
+            let index = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .into_iter()
+                .enumerate()
+                .filter(|(_, (qx, qy))| has_neighbor(mask, qx + cx - 1, qy + cy - 1))
+                .fold(0u8, |acc, (i, _)| acc | 1 << i);
+
+            let quarter = |x: i16, y: i16| FRect {
+                x: x as f32 * 0.5,
+                y: y as f32 * 0.5,
+                w: 0.5,
+                h: 0.5,
+            };
+            TilePart {
+                tile: (index % 4, index / 4),
+                src: quarter(1 - cx, 1 - cy),
+                dst: quarter(cx, cy),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(layout: &TileLayout, mask: u8) -> Vec<(u8, u8)> {
+        layout.parts(NeighborMask(mask)).map(|p| p.tile).collect()
+    }
+
+    #[test]
+    fn test_blob47() {
+        let layout = TileLayout::Blob47;
+        assert_eq!(tiles(&layout, NeighborMask::EMPTY), [(0, 3)]);
+        assert_eq!(tiles(&layout, 0xff), [(9, 2)]);
+        // a loose corner doesn't change the tile
+        assert_eq!(tiles(&layout, NeighborMask::TOP_LEFT), [(0, 3)]);
+    }
+
+    #[test]
+    fn test_edges16() {
+        let layout = TileLayout::Edges16;
+        assert_eq!(tiles(&layout, NeighborMask::EMPTY), [(0, 0)]);
+        assert_eq!(tiles(&layout, NeighborMask::RIGHT), [(2, 0)]);
+        assert_eq!(
+            tiles(&layout, NeighborMask::LEFT | NeighborMask::TOP),
+            [(1, 2)]
+        );
+        assert_eq!(tiles(&layout, 0xff), [(3, 3)]);
+    }
+
+    #[test]
+    fn test_dual_grid() {
+        let layout = TileLayout::DualGrid;
+
+        // alone, each corner only has this tile, which is opposite to it
+        let parts: Vec<_> = layout.parts(NeighborMask(NeighborMask::EMPTY)).collect();
+        assert_eq!(parts.len(), 4);
+        let corners: Vec<_> = parts.iter().map(|p| p.tile).collect();
+        assert_eq!(corners, [(0, 2), (0, 1), (2, 0), (1, 0)]);
+        assert_eq!(parts[0].dst.x, 0.0);
+        assert_eq!(parts[0].src.x, 0.5);
+
+        // surrounded, every corner is full
+        assert!(tiles(&layout, 0xff).iter().all(|t| *t == (3, 3)));
+    }
+
+    #[test]
+    fn test_pick_variant() {
+        assert_eq!(pick_variant(&[], 123), 0);
+        assert_eq!(pick_variant(&[0, 1], 123), 1);
+
+        // stable for the same tile
+        let p = TilePoint::new(-4, 17);
+        assert_eq!(tile_hash(p), tile_hash(p));
+
+        // roughly follows the weights
+        let mut counts = [0; 3];
+        for y in 0..64 {
+            for x in 0..64 {
+                counts[pick_variant(&[6, 1, 1], tile_hash(TilePoint::new(x, y)))] += 1;
+            }
+        }
+        assert!(
+            counts[0] > counts[1] * 4 && counts[0] > counts[2] * 4,
+            "{counts:?}"
+        );
+        assert!(counts[1] > 0 && counts[2] > 0);
+    }
+
+    #[test]
+    fn test_custom() {
+        let layout: TileLayout = serde_json::from_str(
+            r#"{
+                "kind": "custom",
+                "width": 2,
+                "height": 1,
+                "tiles": [
+                    { "mask": 0, "x": 0, "y": 0 },
+                    { "mask": 24, "x": 1, "y": 0 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(layout.validate().is_ok());
+        assert_eq!(layout.size(), (2, 1));
+
+        let left_right = NeighborMask::LEFT | NeighborMask::RIGHT;
+        assert_eq!(tiles(&layout, left_right), [(1, 0)]);
+        assert_eq!(
+            tiles(&layout, left_right | NeighborMask::BOT_LEFT),
+            [(1, 0)]
+        );
+        assert_eq!(tiles(&layout, NeighborMask::TOP), [(0, 0)]);
+
+        let out_of_grid = TileLayout::Custom {
+            width: 1,
+            height: 1,
+            tiles: [CustomTile {
+                mask: 0,
+                x: 1,
+                y: 0,
+            }]
+            .into_iter()
+            .collect(),
+        };
+        assert!(out_of_grid.validate().is_err());
+    }
+}
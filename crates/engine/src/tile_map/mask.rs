@@ -1,6 +1,6 @@
 // DO NOT EDIT: This code was autogenerated.
 
-pub(crate) const MASKS: [(u8, u8); 256] = [
+pub(crate) const BLOB_MASKS: [(u8, u8); 256] = [
     (0, 3),
     (0, 3),
     (0, 2),
@@ -1,10 +1,23 @@
 use std::ops::{BitAnd, BitOr};
 
+use allocator_api2::{
+    alloc::{Allocator, Global as GlobalAllocator},
+    vec::Vec,
+};
 use derivative::Derivative;
-use heapless::Vec;
 
+use crate::coords::TilePoint;
+
+pub mod chunked;
+pub mod collision;
+pub mod flow_field;
+pub mod fov;
+pub mod layout;
 mod mask;
-pub(crate) use mask::MASKS;
+mod open_list;
+pub mod path;
+pub mod raycast;
+pub mod regions;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct NeighborMask(pub u8);
@@ -44,14 +57,6 @@ impl BitAnd<NeighborMask> for NeighborMask {
     }
 }
 
-/// The reason the chunk size is const is because Rust does not allow compile-time
-/// arithmetics like [u8; N * N], and so it can't take a single `const N: usize` param.
-///
-/// See: https://github.com/rust-lang/rust/issues/76560#issue-697556349
-const SIZE: usize = 256;
-const SIZE_PAD: usize = SIZE + 2;
-const SIZE_PAD_SQ: usize = SIZE_PAD * SIZE_PAD;
-
 /// A mask that can be iterated over to find neighbors
 const NEIGHBORS: [(u8, i16, i16); 8] = [
     (NeighborMask::TOP_LEFT, -1, -1),
@@ -66,61 +71,196 @@ const NEIGHBORS: [(u8, i16, i16); 8] = [
 
 /// Stores the tiles in a world and allows querying for them.
 ///
-/// It stores a padding around the map to avoid bounds checking of
-/// neighbors.
+/// It stores a padding of default tiles around the map to avoid bounds checking of
+/// neighbors. The padding is an implementation detail: all coordinates taken by the
+/// public API are logical coordinates that go from `0` to `width - 1` and `height - 1`,
+/// and they're only translated into padded coordinates (shifted by one) internally.
 #[derive(Derivative)]
 #[derivative(Clone(clone_from = "true"))]
 #[derive(Debug)]
-pub struct TileMap<Tile> {
-    map: Vec<Tile, SIZE_PAD_SQ>,
+pub struct TileMap<Tile, A: Allocator + Clone = GlobalAllocator> {
+    width: usize,
+    height: usize,
+    map: Vec<Tile, A>,
+    /// Bumped on every write, so anything derived from the tiles can tell when it's
+    /// out of date
+    revision: u64,
+    /// One bit per tile, in row order, set when it's written to until the changes are
+    /// cleared
+    changed: Vec<u64, A>,
 }
 
-impl<Tile: Default + Clone> Default for TileMap<Tile> {
+impl<Tile: Default + Clone, A: Allocator + Clone + Default> Default for TileMap<Tile, A> {
     fn default() -> Self {
-        let mut map = Vec::new();
-        map.resize_default(SIZE_PAD_SQ).unwrap();
+        Self::new_in(0, 0, A::default())
+    }
+}
 
-        Self { map }
+impl<Tile: Default + Clone, A: Allocator + Clone> TileMap<Tile, A> {
+    /// Create a map of default tiles with the given size, not counting the padding
+    pub fn new_in(width: usize, height: usize, allocator: A) -> Self {
+        let words = (width * height).div_ceil(64);
+        let mut changed = Vec::with_capacity_in(words, allocator.clone());
+        changed.resize(words, 0);
+
+        let len = (width + 2) * (height + 2);
+        let mut map = Vec::with_capacity_in(len, allocator);
+        map.resize(len, Tile::default());
+
+        Self {
+            width,
+            height,
+            map,
+            revision: 0,
+            changed,
+        }
+    }
+
+    /// Reset every tile to the default one
+    pub fn clear(&mut self) {
+        self.map.fill(Tile::default());
+        self.revision += 1;
+        self.mark_all_changed();
     }
 }
 
-impl<Tile> TileMap<Tile> {
-    /// Get the size of the tilemap along one axis
-    pub fn size(&self) -> usize {
-        SIZE
+impl<Tile, A: Allocator + Clone> TileMap<Tile, A> {
+    /// Get the width of the tilemap, not counting the padding
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the height of the tilemap, not counting the padding
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// A number that changes whenever a tile may have been written to, including
+    /// through a mutable ref
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Whether any tile was written to since the changes were last cleared
+    pub fn has_changes(&self) -> bool {
+        self.changed.iter().any(|word| *word != 0)
+    }
+
+    /// Iterate over the tiles written to since the changes were last cleared, in row
+    /// order.
+    ///
+    /// Getting a tile mutable ref counts as writing to it.
+    pub fn changes(&self) -> impl Iterator<Item = TilePoint> {
+        let (width, len) = (self.width, self.width * self.height);
+        self.changed
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| **word != 0)
+            .flat_map(|(i, word)| {
+                let mut bits = *word;
+                std::iter::from_fn(move || {
+                    let bit = bits.trailing_zeros() as usize;
+                    bits &= bits.wrapping_sub(1);
+                    (bit < 64).then_some(i * 64 + bit)
+                })
+            })
+            // the last word has bits past the end when everything was marked
+            .take_while(move |i| *i < len)
+            .map(move |i| TilePoint::new((i % width) as i32, (i / width) as i32))
+    }
+
+    /// Forget which tiles changed, usually once every frame after whatever follows the
+    /// changes had a look at them
+    pub fn clear_changes(&mut self) {
+        self.changed.fill(0);
+    }
+
+    /// Count every tile as changed
+    fn mark_all_changed(&mut self) {
+        self.changed.fill(u64::MAX);
+    }
+
+    /// Whether a point is within the map, not counting the padding
+    pub fn contains(&self, p: TilePoint) -> bool {
+        p.x >= 0 && p.y >= 0 && (p.x as usize) < self.width && (p.y as usize) < self.height
     }
 
-    /// Get a tile ref
+    /// The distance between two rows in the storage
+    fn stride(&self) -> usize {
+        self.width + 2
+    }
+
+    /// Get the storage index of padded coordinates
+    fn padded_index(&self, px: usize, py: usize) -> usize {
+        py * self.stride() + px
+    }
+
+    /// Get the storage index of logical coordinates, panicking if they're out of bounds
+    fn index(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "Tile ({x}, {y}) out of bounds for {}x{} map",
+            self.width,
+            self.height
+        );
+        self.padded_index(x + 1, y + 1)
+    }
+
+    /// Get a tile ref or panic if it's out of bounds
     pub fn get(&self, x: usize, y: usize) -> &Tile {
-        &self.map[x * SIZE + y]
+        &self.map[self.index(x, y)]
     }
 
-    /// Get a tile mutable ref
+    /// Get a tile mutable ref or panic if it's out of bounds
     pub fn get_mut(&mut self, x: usize, y: usize) -> &mut Tile {
-        &mut self.map[x * SIZE + y]
+        let idx = self.index(x, y);
+        self.revision += 1;
+        let i = y * self.width + x;
+        self.changed[i / 64] |= 1 << (i % 64);
+        &mut self.map[idx]
     }
 
+    /// Set a tile or panic if it's out of bounds
     pub fn set(&mut self, x: usize, y: usize, tile: Tile) {
         *self.get_mut(x, y) = tile;
     }
 
+    /// Get a tile ref or `None` if it's out of bounds
+    pub fn try_get(&self, p: TilePoint) -> Option<&Tile> {
+        self.contains(p)
+            .then(|| self.get(p.x as usize, p.y as usize))
+    }
+
+    /// Get a tile mutable ref or `None` if it's out of bounds
+    pub fn try_get_mut(&mut self, p: TilePoint) -> Option<&mut Tile> {
+        if !self.contains(p) {
+            return None;
+        }
+        Some(self.get_mut(p.x as usize, p.y as usize))
+    }
+
+    /// Set a tile and return the one it replaced, or `None` if it's out of bounds
+    pub fn try_set(&mut self, p: TilePoint, tile: Tile) -> Option<Tile> {
+        self.try_get_mut(p).map(|t| std::mem::replace(t, tile))
+    }
+
     /// Iter over a tile's neighbors like so, where T is the tile:
     ///
     /// 1  2  4
     /// 8  T  16
     /// 32 64 128
+    ///
+    /// Neighbors outside of the map are read from the padding, so they are default tiles.
     pub fn iter_neighbors(
         &self,
         x: usize,
         y: usize,
     ) -> impl Iterator<Item = (NeighborMask, &Tile)> {
-        let x = x as i16;
-        let y = y as i16;
+        let idx = self.index(x, y);
+        let stride = self.stride() as isize;
         NEIGHBORS.into_iter().map(move |(np, dx, dy)| {
-            (
-                NeighborMask(np),
-                self.get((x + dx) as usize, (y + dy) as usize),
-            )
+            let offset = dy as isize * stride + dx as isize;
+            (NeighborMask(np), &self.map[idx.wrapping_add_signed(offset)])
         })
     }
 
@@ -134,3 +274,174 @@ impl<Tile> TileMap<Tile> {
             .fold(NeighborMask(0), |acc, (np, _tile)| acc | np)
     }
 }
+
+#[cfg(test)]
+impl<Tile: Default + Clone> TileMap<Tile> {
+    /// Parse a map with one tile per character, for tests to draw the map they use
+    pub(crate) fn from_rows(rows: &[&str], tile: impl Fn(char) -> Tile) -> Self {
+        let mut map = Self::new_in(rows[0].len(), rows.len(), GlobalAllocator);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                map.set(x, y, tile(c));
+            }
+        }
+        map
+    }
+}
+
+/// How tests read the tiles of `TileMap::from_rows`
+#[cfg(test)]
+pub(crate) mod rows {
+    /// `#` is solid
+    pub fn solid(c: char) -> bool {
+        c == '#'
+    }
+
+    /// `#` is a wall, `~` costs 5 and anything else costs 1
+    pub fn cost(c: char) -> Option<f32> {
+        match c {
+            '#' => None,
+            '~' => Some(5.0),
+            _ => Some(1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_map(width: usize, height: usize) -> TileMap<u8> {
+        TileMap::new_in(width, height, GlobalAllocator)
+    }
+
+    #[test]
+    fn test_get_set_logical_coords() {
+        let mut map = new_map(3, 2);
+        for x in 0..3 {
+            for y in 0..2 {
+                map.set(x, y, (x * 10 + y) as u8 + 1);
+            }
+        }
+
+        for x in 0..3 {
+            for y in 0..2 {
+                assert_eq!(*map.get(x, y), (x * 10 + y) as u8 + 1);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_get_out_of_bounds() {
+        let map = new_map(3, 2);
+        map.get(3, 0);
+    }
+
+    #[test]
+    fn test_try_get_set() {
+        let mut map = new_map(3, 2);
+
+        assert_eq!(map.try_set(TilePoint::new(2, 1), 7), Some(0));
+        assert_eq!(map.try_get(TilePoint::new(2, 1)), Some(&7));
+
+        for p in [
+            TilePoint::new(-1, 0),
+            TilePoint::new(0, -1),
+            TilePoint::new(3, 0),
+            TilePoint::new(0, 2),
+        ] {
+            assert_eq!(map.try_get(p), None);
+            assert_eq!(map.try_get_mut(p), None);
+            assert_eq!(map.try_set(p, 1), None);
+        }
+    }
+
+    #[test]
+    fn test_revision() {
+        let mut map = new_map(3, 2);
+        let start = map.revision();
+
+        map.get(0, 0);
+        map.try_get(TilePoint::new(5, 5));
+        map.try_set(TilePoint::new(5, 5), 1);
+        assert_eq!(map.revision(), start);
+
+        map.set(1, 1, 3);
+        let set = map.revision();
+        assert!(set > start);
+
+        map.clear();
+        assert!(map.revision() > set);
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut map = new_map(70, 2);
+        assert!(!map.has_changes());
+
+        map.set(69, 0, 1);
+        map.set(3, 1, 1);
+        map.try_set(TilePoint::new(1, 0), 1);
+        map.try_set(TilePoint::new(70, 0), 1);
+        map.get(5, 1);
+        let changes: Vec<_> = map.changes().collect();
+        assert_eq!(
+            changes[..],
+            [
+                TilePoint::new(1, 0),
+                TilePoint::new(69, 0),
+                TilePoint::new(3, 1)
+            ]
+        );
+
+        map.clear_changes();
+        assert!(!map.has_changes());
+        assert_eq!(map.changes().count(), 0);
+
+        map.clear();
+        assert_eq!(map.changes().count(), 140);
+    }
+
+    #[test]
+    fn test_neighbors_at_corners() {
+        let mut map = new_map(2, 2);
+        map.set(0, 0, 1);
+        map.set(1, 0, 1);
+        map.set(0, 1, 1);
+        map.set(1, 1, 1);
+
+        assert_eq!(
+            map.filter_neighbors(0, 0, |t| *t == 1),
+            NeighborMask(NeighborMask::RIGHT | NeighborMask::BOT | NeighborMask::BOT_RIGHT)
+        );
+        assert_eq!(
+            map.filter_neighbors(1, 1, |t| *t == 1),
+            NeighborMask(NeighborMask::TOP_LEFT | NeighborMask::TOP | NeighborMask::LEFT)
+        );
+        // the padding is all default tiles
+        assert_eq!(
+            map.filter_neighbors(1, 0, |t| *t == 0),
+            NeighborMask(
+                NeighborMask::TOP_LEFT
+                    | NeighborMask::TOP
+                    | NeighborMask::TOP_RIGHT
+                    | NeighborMask::RIGHT
+                    | NeighborMask::BOT_RIGHT
+            )
+        );
+    }
+
+    #[test]
+    fn test_neighbors_at_edges() {
+        let mut map = new_map(3, 3);
+        map.set(1, 0, 1);
+        map.set(0, 2, 1);
+
+        assert_eq!(
+            map.filter_neighbors(0, 1, |t| *t == 1),
+            NeighborMask(NeighborMask::TOP_RIGHT | NeighborMask::BOT)
+        );
+        assert_eq!(map.iter_neighbors(0, 1).count(), 8);
+    }
+}
@@ -0,0 +1,67 @@
+use allocator_api2::{alloc::Allocator, vec::Vec};
+
+use crate::coords::TilePoint;
+
+/// A tile waiting to be explored, ordered by its cost
+#[derive(Copy, Clone, Debug)]
+pub(super) struct OpenTile {
+    pub cost: f32,
+    pub p: TilePoint,
+}
+
+/// A binary min-heap of tiles to explore, cheapest first
+pub(super) struct OpenList<A: Allocator> {
+    heap: Vec<OpenTile, A>,
+}
+
+impl<A: Allocator> OpenList<A> {
+    pub fn new_in(allocator: A) -> Self {
+        Self {
+            heap: Vec::new_in(allocator),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+    }
+
+    pub fn push(&mut self, cost: f32, p: TilePoint) {
+        self.heap.push(OpenTile { cost, p });
+
+        let mut i = self.heap.len() - 1;
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[parent].cost <= self.heap[i].cost {
+                break;
+            }
+            self.heap.swap(parent, i);
+            i = parent;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<OpenTile> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        let top = self.heap.swap_remove(0);
+
+        let mut i = 0;
+        loop {
+            let (left, right) = (i * 2 + 1, i * 2 + 2);
+            let mut smallest = i;
+            if left < self.heap.len() && self.heap[left].cost < self.heap[smallest].cost {
+                smallest = left;
+            }
+            if right < self.heap.len() && self.heap[right].cost < self.heap[smallest].cost {
+                smallest = right;
+            }
+            if smallest == i {
+                break;
+            }
+            self.heap.swap(smallest, i);
+            i = smallest;
+        }
+
+        Some(top)
+    }
+}
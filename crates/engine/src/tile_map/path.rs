@@ -0,0 +1,302 @@
+use std::f32::consts::SQRT_2;
+
+use allocator_api2::{alloc::Allocator, vec::Vec};
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+use crate::coords::{TilePoint, TileVector};
+
+use super::{
+    NEIGHBORS, TileMap,
+    open_list::{OpenList, OpenTile},
+};
+
+/// How diagonal moves treat the tiles next to the corner they go around
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DiagonalMoves {
+    /// Only move up, down, left and right
+    Never,
+    /// Move diagonally only when both tiles next to the corner are passable
+    NoCornerCutting,
+    /// Move diagonally when at least one of the tiles next to the corner is passable
+    CutCorners,
+    /// Move diagonally even between two impassable tiles
+    Always,
+}
+
+impl DiagonalMoves {
+    pub(super) fn allows(self, open_x: bool, open_y: bool) -> bool {
+        match self {
+            Self::Never => false,
+            Self::NoCornerCutting => open_x && open_y,
+            Self::CutCorners => open_x || open_y,
+            Self::Always => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct VisitedTile {
+    /// The cost of the cheapest known path from the start
+    cost: f32,
+    parent: TilePoint,
+    closed: bool,
+}
+
+/// Finds the cheapest paths between tiles with A*.
+///
+/// It keeps its buffers between searches so that repeated searches don't allocate.
+pub struct Pathfinder<A: Allocator + Clone> {
+    pub diagonals: DiagonalMoves,
+    /// The number of tiles explored before giving up on a search
+    pub max_explored: usize,
+
+    /// Tiles to explore, ordered by their estimated total cost
+    open: OpenList<A>,
+    visited: HashMap<TilePoint, VisitedTile, DefaultHashBuilder, A>,
+}
+
+impl<A: Allocator + Clone> Pathfinder<A> {
+    pub fn new_in(allocator: A, diagonals: DiagonalMoves, max_explored: usize) -> Self {
+        Self {
+            diagonals,
+            max_explored,
+            open: OpenList::new_in(allocator.clone()),
+            visited: HashMap::new_in(allocator),
+        }
+    }
+
+    /// Estimate the cost between two tiles, assuming every tile costs at least 1
+    fn heuristic(&self, a: TilePoint, b: TilePoint) -> f32 {
+        let dx = (a.x - b.x).unsigned_abs() as f32;
+        let dy = (a.y - b.y).unsigned_abs() as f32;
+        match self.diagonals {
+            DiagonalMoves::Never => dx + dy,
+            _ => dx + dy + (SQRT_2 - 2.0) * dx.min(dy),
+        }
+    }
+
+    /// Find the cheapest path from `start` to `goal` and write it into `path`, without
+    /// the start and with the goal. Returns whether a path was found.
+    ///
+    /// `cost` is the cost of stepping onto a tile or `None` if it's impassable. Costs
+    /// below 1 may give paths that aren't the cheapest. Diagonal steps cost `SQRT_2`
+    /// times more.
+    pub fn find_path<C, B>(
+        &mut self,
+        start: TilePoint,
+        goal: TilePoint,
+        cost: C,
+        path: &mut Vec<TilePoint, B>,
+    ) -> bool
+    where
+        C: Fn(TilePoint) -> Option<f32>,
+        B: Allocator,
+    {
+        path.clear();
+        self.open.clear();
+        self.visited.clear();
+
+        if cost(goal).is_none() {
+            return false;
+        }
+
+        self.visited.insert(
+            start,
+            VisitedTile {
+                cost: 0.0,
+                parent: start,
+                closed: false,
+            },
+        );
+        self.open.push(self.heuristic(start, goal), start);
+
+        let mut explored = 0;
+        while let Some(OpenTile { p, .. }) = self.open.pop() {
+            let tile = self.visited.get_mut(&p).expect("Open tiles are visited");
+            if tile.closed {
+                continue;
+            }
+            tile.closed = true;
+            let tile = *tile;
+
+            if p == goal {
+                self.write_path(start, goal, path);
+                return true;
+            }
+
+            explored += 1;
+            if explored > self.max_explored {
+                return false;
+            }
+
+            for (_, dx, dy) in NEIGHBORS {
+                let np = p + TileVector::new(dx as i32, dy as i32);
+                let Some(step_cost) = cost(np) else {
+                    continue;
+                };
+
+                let step_cost = if dx != 0 && dy != 0 {
+                    let open_x = cost(p + TileVector::new(dx as i32, 0)).is_some();
+                    let open_y = cost(p + TileVector::new(0, dy as i32)).is_some();
+                    if !self.diagonals.allows(open_x, open_y) {
+                        continue;
+                    }
+                    step_cost * SQRT_2
+                } else {
+                    step_cost
+                };
+
+                let new_cost = tile.cost + step_cost;
+                if let Some(neighbor) = self.visited.get(&np)
+                    && (neighbor.closed || neighbor.cost <= new_cost)
+                {
+                    continue;
+                }
+
+                self.visited.insert(
+                    np,
+                    VisitedTile {
+                        cost: new_cost,
+                        parent: p,
+                        closed: false,
+                    },
+                );
+                self.open.push(new_cost + self.heuristic(np, goal), np);
+            }
+        }
+
+        false
+    }
+
+    /// Find a path within a tile map, where tiles out of the map are impassable.
+    ///
+    /// See `find_path`
+    pub fn find_path_in<Tile, MA, C, B>(
+        &mut self,
+        map: &TileMap<Tile, MA>,
+        start: TilePoint,
+        goal: TilePoint,
+        cost: C,
+        path: &mut Vec<TilePoint, B>,
+    ) -> bool
+    where
+        MA: Allocator + Clone,
+        C: Fn(&Tile) -> Option<f32>,
+        B: Allocator,
+    {
+        self.find_path(start, goal, |p| map.try_get(p).and_then(&cost), path)
+    }
+
+    /// Follow the parents from the goal back to the start
+    fn write_path<B: Allocator>(
+        &self,
+        start: TilePoint,
+        goal: TilePoint,
+        path: &mut Vec<TilePoint, B>,
+    ) {
+        let mut p = goal;
+        while p != start {
+            path.push(p);
+            p = self.visited[&p].parent;
+        }
+        path.reverse();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+    use crate::tile_map::rows;
+
+    fn find(
+        map: &TileMap<Option<f32>, Global>,
+        diagonals: DiagonalMoves,
+        start: (i32, i32),
+        goal: (i32, i32),
+    ) -> Option<Vec<TilePoint, Global>> {
+        let mut pathfinder = Pathfinder::new_in(Global, diagonals, 1000);
+        let mut path = Vec::new_in(Global);
+        let start = TilePoint::new(start.0, start.1);
+        let goal = TilePoint::new(goal.0, goal.1);
+        pathfinder
+            .find_path_in(map, start, goal, |c| *c, &mut path)
+            .then_some(path)
+    }
+
+    #[test]
+    fn test_straight_path() {
+        let map = TileMap::from_rows(&["....."], rows::cost);
+        let path = find(&map, DiagonalMoves::NoCornerCutting, (0, 0), (4, 0)).unwrap();
+        assert_eq!(path.as_slice(), &[1, 2, 3, 4].map(|x| TilePoint::new(x, 0)));
+    }
+
+    #[test]
+    fn test_path_around_wall() {
+        let map = TileMap::from_rows(
+            &[
+                "...", //
+                ".#.", //
+                "...",
+            ],
+            rows::cost,
+        );
+
+        let path = find(&map, DiagonalMoves::NoCornerCutting, (0, 1), (2, 1)).unwrap();
+        // around a corner, then along the wall, then around another corner
+        assert_eq!(path.len(), 4);
+        assert!(path.iter().all(|p| *p != TilePoint::new(1, 1)));
+
+        let path = find(&map, DiagonalMoves::CutCorners, (0, 1), (2, 1)).unwrap();
+        assert_eq!(path.len(), 2);
+    }
+
+    #[test]
+    fn test_no_diagonals() {
+        let map = TileMap::from_rows(&["..", ".."], rows::cost);
+        let path = find(&map, DiagonalMoves::Never, (0, 0), (1, 1)).unwrap();
+        assert_eq!(path.len(), 2);
+
+        let path = find(&map, DiagonalMoves::NoCornerCutting, (0, 0), (1, 1)).unwrap();
+        assert_eq!(path.as_slice(), &[TilePoint::new(1, 1)]);
+    }
+
+    #[test]
+    fn test_corner_cutting() {
+        let map = TileMap::from_rows(
+            &[
+                ".#", //
+                "#.",
+            ],
+            rows::cost,
+        );
+
+        assert!(find(&map, DiagonalMoves::NoCornerCutting, (0, 0), (1, 1)).is_none());
+        assert!(find(&map, DiagonalMoves::CutCorners, (0, 0), (1, 1)).is_none());
+        assert!(find(&map, DiagonalMoves::Always, (0, 0), (1, 1)).is_some());
+    }
+
+    #[test]
+    fn test_prefer_cheaper_tiles() {
+        let map = TileMap::from_rows(
+            &[
+                ".~.", //
+                "...",
+            ],
+            rows::cost,
+        );
+
+        let path = find(&map, DiagonalMoves::Never, (0, 0), (2, 0)).unwrap();
+        assert_eq!(path.len(), 4);
+        assert!(path.iter().all(|p| *p != TilePoint::new(1, 0)));
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let map = TileMap::from_rows(&[".#."], rows::cost);
+        assert!(find(&map, DiagonalMoves::Always, (0, 0), (2, 0)).is_none());
+        assert!(find(&map, DiagonalMoves::Always, (0, 0), (1, 0)).is_none());
+        assert!(find(&map, DiagonalMoves::Always, (0, 0), (9, 0)).is_none());
+    }
+}
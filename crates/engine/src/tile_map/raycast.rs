@@ -0,0 +1,334 @@
+use std::cmp::Ordering;
+
+use allocator_api2::alloc::Allocator;
+use euclid::{Point2D, Vector2D};
+
+use crate::coords::{TilePoint, TileSpace, TileVector};
+
+use super::TileMap;
+
+/// A tile crossed by a ray
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayStep {
+    pub tile: TilePoint,
+    /// How far along the ray it entered the tile
+    pub distance: f64,
+    /// The side of the tile the ray entered through, pointing out of the tile. It's zero
+    /// for the tile the ray starts in
+    pub normal: TileVector,
+}
+
+/// Where a ray hit a solid tile
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayHit {
+    pub tile: TilePoint,
+    /// How far along the ray the tile was hit
+    pub distance: f64,
+    /// The side of the tile that was hit, pointing out of the tile. It's zero if the ray
+    /// started inside it
+    pub normal: TileVector,
+    pub point: Point2D<f64, TileSpace>,
+}
+
+/// Iterates over every tile crossed by a ray, in order, with a DDA walk.
+///
+/// Tile `(x, y)` covers the area from `(x, y)` to `(x + 1, y + 1)`. When the ray goes
+/// exactly through a corner, it steps vertically first.
+pub struct TileRay {
+    next: Option<RayStep>,
+    max_distance: f64,
+    /// Which way the tile coordinates change along the ray
+    step: TileVector,
+    /// How far along the ray the next vertical and horizontal tile borders are
+    border: Vector2D<f64, TileSpace>,
+    /// How far along the ray it is from one tile border to the next
+    delta: Vector2D<f64, TileSpace>,
+}
+
+impl TileRay {
+    /// Start a ray that stops after `max_distance` tiles. A zero direction only crosses
+    /// the starting tile
+    pub fn new(
+        origin: Point2D<f64, TileSpace>,
+        direction: Vector2D<f64, TileSpace>,
+        max_distance: f64,
+    ) -> Self {
+        let start = origin.floor().cast::<i32>();
+        let direction = if direction == Vector2D::zero() {
+            direction
+        } else {
+            direction.normalize()
+        };
+
+        let axis = |origin: f64, tile: i32, direction: f64| {
+            if direction > 0.0 {
+                (1, (tile as f64 + 1.0 - origin) / direction, 1.0 / direction)
+            } else if direction < 0.0 {
+                (-1, (origin - tile as f64) / -direction, -1.0 / direction)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_x, border_x, delta_x) = axis(origin.x, start.x, direction.x);
+        let (step_y, border_y, delta_y) = axis(origin.y, start.y, direction.y);
+
+        Self {
+            next: Some(RayStep {
+                tile: start,
+                distance: 0.0,
+                normal: TileVector::zero(),
+            }),
+            max_distance,
+            step: TileVector::new(step_x, step_y),
+            border: Vector2D::new(border_x, border_y),
+            delta: Vector2D::new(delta_x, delta_y),
+        }
+    }
+}
+
+impl Iterator for TileRay {
+    type Item = RayStep;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+
+        let mut next = current;
+        if self.border.x < self.border.y {
+            next.distance = self.border.x;
+            next.tile.x += self.step.x;
+            next.normal = TileVector::new(-self.step.x, 0);
+            self.border.x += self.delta.x;
+        } else {
+            next.distance = self.border.y;
+            next.tile.y += self.step.y;
+            next.normal = TileVector::new(0, -self.step.y);
+            self.border.y += self.delta.y;
+        }
+        // the borders are infinitely far when the direction is zero, so even a ray
+        // without a maximum distance stops there
+        self.next =
+            (next.distance.is_finite() && next.distance <= self.max_distance).then_some(next);
+
+        Some(current)
+    }
+}
+
+/// Cast a ray and find the first tile it hits within `max_distance`, including the tile
+/// it starts in
+pub fn cast_ray<F>(
+    origin: Point2D<f64, TileSpace>,
+    direction: Vector2D<f64, TileSpace>,
+    max_distance: f64,
+    is_solid: F,
+) -> Option<RayHit>
+where
+    F: Fn(TilePoint) -> bool,
+{
+    let unit = if direction == Vector2D::zero() {
+        direction
+    } else {
+        direction.normalize()
+    };
+
+    TileRay::new(origin, direction, max_distance)
+        .find(|step| is_solid(step.tile))
+        .map(|step| RayHit {
+            tile: step.tile,
+            distance: step.distance,
+            normal: step.normal,
+            point: origin + unit * step.distance,
+        })
+}
+
+/// Whether the line between the centers of two tiles goes through no blocking tile.
+///
+/// The two tiles themselves can block, so a wall can be seen. When the line goes exactly
+/// through a corner, it's blocked if either of the tiles beside the corner blocks, so
+/// nothing is seen through the gap between two diagonal walls and the result is the same
+/// both ways.
+pub fn line_of_sight<F>(from: TilePoint, to: TilePoint, blocks: F) -> bool
+where
+    F: Fn(TilePoint) -> bool,
+{
+    let line = to - from;
+    let step = TileVector::new(line.x.signum(), line.y.signum());
+    let (len_x, len_y) = (line.x.unsigned_abs() as i64, line.y.unsigned_abs() as i64);
+
+    // walked with integers, as the corners have to be found exactly
+    let mut tile = from;
+    let (mut crossed_x, mut crossed_y) = (0i64, 0i64);
+    while crossed_x < len_x || crossed_y < len_y {
+        // which of the next vertical and horizontal tile borders the line crosses first
+        match ((1 + 2 * crossed_x) * len_y).cmp(&((1 + 2 * crossed_y) * len_x)) {
+            Ordering::Less => {
+                tile.x += step.x;
+                crossed_x += 1;
+            }
+            Ordering::Greater => {
+                tile.y += step.y;
+                crossed_y += 1;
+            }
+            Ordering::Equal => {
+                if blocks(tile + TileVector::new(step.x, 0))
+                    || blocks(tile + TileVector::new(0, step.y))
+                {
+                    return false;
+                }
+                tile += step;
+                crossed_x += 1;
+                crossed_y += 1;
+            }
+        }
+
+        if tile != to && blocks(tile) {
+            return false;
+        }
+    }
+
+    true
+}
+
+impl<Tile, A: Allocator + Clone> TileMap<Tile, A> {
+    /// Cast a ray within the map, where tiles out of the map are never solid.
+    ///
+    /// See `cast_ray`
+    pub fn cast_ray<F>(
+        &self,
+        origin: Point2D<f64, TileSpace>,
+        direction: Vector2D<f64, TileSpace>,
+        max_distance: f64,
+        is_solid: F,
+    ) -> Option<RayHit>
+    where
+        F: Fn(&Tile) -> bool,
+    {
+        cast_ray(origin, direction, max_distance, |p| {
+            self.try_get(p).is_some_and(&is_solid)
+        })
+    }
+
+    /// Whether two tiles in the map can see each other, where tiles out of the map never
+    /// block.
+    ///
+    /// See `line_of_sight`
+    pub fn line_of_sight<F>(&self, from: TilePoint, to: TilePoint, blocks: F) -> bool
+    where
+        F: Fn(&Tile) -> bool,
+    {
+        line_of_sight(from, to, |p| self.try_get(p).is_some_and(&blocks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::vec::Vec;
+
+    use super::*;
+    use crate::tile_map::rows;
+
+    #[test]
+    fn test_traversed_tiles() {
+        let tiles: Vec<_> = TileRay::new(Point2D::new(0.5, 0.5), Vector2D::new(2.0, 1.0), 2.0)
+            .map(|step| (step.tile.x, step.tile.y))
+            .collect();
+        assert_eq!(tiles, [(0, 0), (1, 0), (1, 1), (2, 1)]);
+
+        let steps: Vec<_> =
+            TileRay::new(Point2D::new(0.5, 0.5), Vector2D::new(-1.0, 0.0), 2.0).collect();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2].tile, TilePoint::new(-2, 0));
+        assert_eq!(steps[2].distance, 1.5);
+        assert_eq!(steps[2].normal, TileVector::new(1, 0));
+    }
+
+    #[test]
+    fn test_zero_direction() {
+        let steps: Vec<_> =
+            TileRay::new(Point2D::new(0.5, 0.5), Vector2D::zero(), f64::INFINITY).collect();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].tile, TilePoint::new(0, 0));
+    }
+
+    #[test]
+    fn test_hit_solid_tile() {
+        let map = TileMap::from_rows(
+            &[
+                "....", //
+                "...#", //
+                "....",
+            ],
+            rows::solid,
+        );
+
+        let hit = map
+            .cast_ray(Point2D::new(0.5, 1.5), Vector2D::new(1.0, 0.0), 10.0, |t| {
+                *t
+            })
+            .unwrap();
+        assert_eq!(hit.tile, TilePoint::new(3, 1));
+        assert_eq!(hit.normal, TileVector::new(-1, 0));
+        assert_eq!(hit.distance, 2.5);
+        assert_eq!(hit.point, Point2D::new(3.0, 1.5));
+
+        let hit = map.cast_ray(Point2D::new(3.5, 0.5), Vector2D::new(0.0, 1.0), 10.0, |t| {
+            *t
+        });
+        assert_eq!(hit.unwrap().normal, TileVector::new(0, -1));
+
+        // too short or going the other way
+        let miss = map.cast_ray(Point2D::new(0.5, 1.5), Vector2D::new(1.0, 0.0), 2.0, |t| *t);
+        assert!(miss.is_none());
+        let miss = map.cast_ray(
+            Point2D::new(0.5, 1.5),
+            Vector2D::new(-1.0, 0.0),
+            10.0,
+            |t| *t,
+        );
+        assert!(miss.is_none());
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let map = TileMap::from_rows(
+            &[
+                ".....", //
+                "..#..", //
+                ".....",
+            ],
+            rows::solid,
+        );
+
+        let p = TilePoint::new;
+        assert!(!map.line_of_sight(p(0, 1), p(4, 1), |t| *t));
+        assert!(map.line_of_sight(p(0, 0), p(4, 0), |t| *t));
+        assert!(map.line_of_sight(p(0, 1), p(2, 1), |t| *t));
+        assert!(map.line_of_sight(p(2, 1), p(2, 1), |t| *t));
+        assert_eq!(
+            map.line_of_sight(p(0, 0), p(4, 2), |t| *t),
+            map.line_of_sight(p(4, 2), p(0, 0), |t| *t)
+        );
+    }
+
+    #[test]
+    fn test_line_of_sight_through_corner() {
+        let map = TileMap::from_rows(
+            &[
+                "....", //
+                "#...", //
+                "....",
+            ],
+            rows::solid,
+        );
+
+        let p = TilePoint::new;
+        // exactly through the corner of the wall, either way
+        assert!(!map.line_of_sight(p(0, 0), p(1, 1), |t| *t));
+        assert!(!map.line_of_sight(p(1, 1), p(0, 0), |t| *t));
+        // through two corners, the last one next to the wall
+        assert!(!map.line_of_sight(p(2, 2), p(0, 0), |t| *t));
+        // through corners away from the wall
+        assert!(map.line_of_sight(p(1, 0), p(2, 1), |t| *t));
+        assert!(map.line_of_sight(p(0, 2), p(3, 1), |t| *t));
+        assert!(map.line_of_sight(p(3, 1), p(0, 2), |t| *t));
+    }
+}
@@ -0,0 +1,302 @@
+use allocator_api2::{alloc::Allocator, vec::Vec};
+
+use crate::coords::{TilePoint, TileVector};
+
+use super::{NEIGHBORS, TileMap};
+
+/// Which tiles count as touching each other
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Tiles only touch up, down, left and right
+    Four,
+    /// Tiles also touch diagonally
+    Eight,
+}
+
+impl Connectivity {
+    /// The steps to the tiles touching a tile
+    fn steps(self) -> impl Iterator<Item = TileVector> {
+        NEIGHBORS
+            .into_iter()
+            .filter(move |(_, dx, dy)| self == Self::Eight || dx * dy == 0)
+            .map(|(_, dx, dy)| TileVector::new(dx as i32, dy as i32))
+    }
+}
+
+/// The ID of a connected region of tiles, starting at 1
+pub type RegionId = u32;
+
+/// The connected regions of a tile map, where each tile knows which region it's in.
+pub struct RegionLabels<A: Allocator + Clone> {
+    /// The region of each tile, or 0 if it's not in any
+    labels: TileMap<RegionId, A>,
+    /// The number of tiles in each region, indexed by `RegionId - 1`
+    sizes: Vec<usize, A>,
+}
+
+impl<A: Allocator + Clone> RegionLabels<A> {
+    /// The number of regions
+    pub fn count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// The region of a tile, or `None` if it's not in any
+    pub fn region(&self, p: TilePoint) -> Option<RegionId> {
+        self.labels.try_get(p).copied().filter(|label| *label != 0)
+    }
+
+    /// The number of tiles in a region
+    pub fn size(&self, region: RegionId) -> usize {
+        self.sizes[region as usize - 1]
+    }
+
+    /// Whether two tiles are in the same region, so one can be reached from the other
+    pub fn connected(&self, a: TilePoint, b: TilePoint) -> bool {
+        self.region(a)
+            .is_some_and(|region| self.region(b) == Some(region))
+    }
+
+    /// Iterate over the tiles of a region
+    pub fn iter(&self, region: RegionId) -> impl Iterator<Item = TilePoint> {
+        let (width, height) = (self.labels.width(), self.labels.height());
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| (x, y)))
+            .filter(move |(x, y)| *self.labels.get(*x, *y) == region)
+            .map(|(x, y)| TilePoint::new(x as i32, y as i32))
+    }
+
+    /// Iterate over the tiles of a region that touch a tile outside of it, up, down,
+    /// left or right
+    pub fn boundary(&self, region: RegionId) -> impl Iterator<Item = TilePoint> {
+        self.iter(region).filter(move |p| {
+            Connectivity::Four
+                .steps()
+                .any(|step| self.region(*p + step) != Some(region))
+        })
+    }
+}
+
+impl<Tile, A: Allocator + Clone> TileMap<Tile, A> {
+    /// Find the tiles matching `predicate` that are connected to `start`, including
+    /// itself, and write them into `tiles`. Nothing is found if `start` doesn't match
+    pub fn flood_fill<F, B>(
+        &self,
+        start: TilePoint,
+        connectivity: Connectivity,
+        predicate: F,
+        tiles: &mut Vec<TilePoint, B>,
+    ) where
+        F: Fn(&Tile) -> bool,
+        B: Allocator,
+    {
+        tiles.clear();
+        if !self.try_get(start).is_some_and(&predicate) {
+            return;
+        }
+
+        let allocator = self.map.allocator().clone();
+        let mut visited = TileMap::<bool, A>::new_in(self.width, self.height, allocator);
+        visited.set(start.x as usize, start.y as usize, true);
+        tiles.push(start);
+        self.spread(connectivity, &predicate, tiles, |p| {
+            visited.try_set(p, true) == Some(false)
+        });
+    }
+
+    /// Grow `tiles` with the tiles matching `predicate` connected to the ones in it.
+    /// `visit` marks a tile as found, returning `false` if it already was
+    fn spread<F, V, B>(
+        &self,
+        connectivity: Connectivity,
+        predicate: F,
+        tiles: &mut Vec<TilePoint, B>,
+        mut visit: V,
+    ) where
+        F: Fn(&Tile) -> bool,
+        V: FnMut(TilePoint) -> bool,
+        B: Allocator,
+    {
+        // the found tiles double as the queue of tiles to spread from
+        let mut i = 0;
+        while i < tiles.len() {
+            let p = tiles[i];
+            for step in connectivity.steps() {
+                let np = p + step;
+                if self.try_get(np).is_some_and(&predicate) && visit(np) {
+                    tiles.push(np);
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Replace the tiles connected to `start` that are the same as it with `tile`, like
+    /// a paint bucket. Returns how many tiles were replaced
+    pub fn bucket_fill(&mut self, start: TilePoint, connectivity: Connectivity, tile: Tile) -> usize
+    where
+        Tile: Clone + PartialEq,
+    {
+        let Some(old) = self.try_get(start).cloned() else {
+            return 0;
+        };
+        if old == tile {
+            return 0;
+        }
+
+        let mut tiles = Vec::new_in(self.map.allocator().clone());
+        self.flood_fill(start, connectivity, |t| *t == old, &mut tiles);
+        for p in &tiles {
+            self.set(p.x as usize, p.y as usize, tile.clone());
+        }
+        tiles.len()
+    }
+
+    /// Split the tiles matching `predicate` into connected regions, like rooms or
+    /// islands
+    pub fn label_regions<F>(&self, connectivity: Connectivity, predicate: F) -> RegionLabels<A>
+    where
+        F: Fn(&Tile) -> bool,
+    {
+        let allocator = self.map.allocator().clone();
+        let mut labels = RegionLabels {
+            labels: TileMap::new_in(self.width, self.height, allocator.clone()),
+            sizes: Vec::new_in(allocator.clone()),
+        };
+        let mut tiles = Vec::new_in(allocator);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if *labels.labels.get(x, y) != 0 || !predicate(self.get(x, y)) {
+                    continue;
+                }
+
+                // the labels double as the visited tiles, so each tile is only visited once
+                let region = labels.sizes.len() as RegionId + 1;
+                labels.labels.set(x, y, region);
+                tiles.clear();
+                tiles.push(TilePoint::new(x as i32, y as i32));
+                self.spread(connectivity, &predicate, &mut tiles, |p| {
+                    labels.labels.try_set(p, region) == Some(0)
+                });
+                labels.sizes.push(tiles.len());
+            }
+        }
+
+        labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+
+    use super::*;
+
+    #[test]
+    fn test_flood_fill() {
+        let map = TileMap::from_rows(
+            &[
+                "..#..", //
+                "..#..", //
+                "###.#",
+            ],
+            |c| c,
+        );
+        let mut tiles = Vec::new_in(Global);
+
+        map.flood_fill(
+            TilePoint::new(0, 0),
+            Connectivity::Four,
+            |c| *c == '.',
+            &mut tiles,
+        );
+        assert_eq!(tiles.len(), 4);
+
+        map.flood_fill(
+            TilePoint::new(3, 0),
+            Connectivity::Four,
+            |c| *c == '.',
+            &mut tiles,
+        );
+        assert_eq!(tiles.len(), 5);
+
+        map.flood_fill(
+            TilePoint::new(2, 0),
+            Connectivity::Four,
+            |c| *c == '.',
+            &mut tiles,
+        );
+        assert!(tiles.is_empty());
+    }
+
+    #[test]
+    fn test_bucket_fill() {
+        let mut map = TileMap::from_rows(
+            &[
+                "..#", //
+                ".#.", //
+                "#..",
+            ],
+            |c| c,
+        );
+
+        assert_eq!(
+            map.bucket_fill(TilePoint::new(0, 0), Connectivity::Four, 'x'),
+            3
+        );
+        assert_eq!(*map.get(2, 2), '.');
+        assert_eq!(
+            map.bucket_fill(TilePoint::new(0, 0), Connectivity::Four, 'x'),
+            0
+        );
+
+        // diagonal walls don't stop diagonal fills
+        assert_eq!(
+            map.bucket_fill(TilePoint::new(0, 0), Connectivity::Eight, '.'),
+            3
+        );
+        assert_eq!(
+            map.bucket_fill(TilePoint::new(0, 0), Connectivity::Eight, 'o'),
+            6
+        );
+    }
+
+    #[test]
+    fn test_label_regions() {
+        let map = TileMap::from_rows(
+            &[
+                "..#..", //
+                "..#..", //
+                "#####", //
+                "....#",
+            ],
+            |c| c,
+        );
+        let labels = map.label_regions(Connectivity::Four, |c| *c == '.');
+
+        assert_eq!(labels.count(), 3);
+        let p = TilePoint::new;
+        assert!(labels.connected(p(0, 0), p(1, 1)));
+        assert!(!labels.connected(p(0, 0), p(3, 0)));
+        assert!(!labels.connected(p(0, 0), p(2, 0)));
+        assert_eq!(labels.region(p(2, 2)), None);
+
+        let bottom = labels.region(p(0, 3)).unwrap();
+        assert_eq!(labels.size(bottom), 4);
+
+        let top_left = labels.region(p(0, 0)).unwrap();
+        assert_eq!(labels.iter(top_left).count(), 4);
+        // every tile of a 2x2 room is on its boundary
+        assert_eq!(labels.boundary(top_left).count(), 4);
+    }
+
+    #[test]
+    fn test_region_boundary() {
+        let map = TileMap::from_rows(&["....", "....", "...."], |c| c);
+        let labels = map.label_regions(Connectivity::Eight, |c| *c == '.');
+
+        assert_eq!(labels.count(), 1);
+        assert_eq!(labels.boundary(1).count(), 10);
+        assert!(labels.boundary(1).all(|p| p != TilePoint::new(1, 1)));
+    }
+}
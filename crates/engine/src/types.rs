@@ -11,11 +11,11 @@ use serde::{Deserialize, Serialize};
 pub struct Id<T>(pub u32, PhantomData<T>);
 
 impl<T> Id<T> {
-    pub fn new(val: u32) -> Self {
+    pub const fn new(val: u32) -> Self {
         Self(val, PhantomData)
     }
 
-    pub fn new_split(hi: u16, lo: u16) -> Self {
+    pub const fn new_split(hi: u16, lo: u16) -> Self {
         Self::new(((hi as u32) << 16) | (lo as u32))
     }
 
@@ -1,4 +1,5 @@
-use engine::coords::{TilePoint, WorldPoint};
+use engine::coords::{TilePoint, TileSpace, WorldPoint};
+use euclid::Point2D;
 
 /// Conversion factor of world meters to pixels
 pub const WORLD_TO_PIXEL: f64 = 3.0;
@@ -9,7 +10,22 @@ pub const WORLD_TO_TILE: f64 = 1.0;
 /// Convert a world position into a tile position
 pub fn world_to_tile(world: WorldPoint) -> TilePoint {
     TilePoint::new(
-        (world.x * WORLD_TO_TILE) as u16,
-        (world.y * WORLD_TO_TILE) as u16,
+        (world.x * WORLD_TO_TILE).floor() as i32,
+        (world.y * WORLD_TO_TILE).floor() as i32,
     )
 }
+
+/// Convert a world position into a fractional tile position
+pub fn world_to_fractional_tile(world: WorldPoint) -> Point2D<f64, TileSpace> {
+    Point2D::new(world.x * WORLD_TO_TILE, world.y * WORLD_TO_TILE)
+}
+
+/// Convert a fractional tile position into a world position
+pub fn tile_to_world(tile: Point2D<f64, TileSpace>) -> WorldPoint {
+    WorldPoint::new(tile.x / WORLD_TO_TILE, tile.y / WORLD_TO_TILE)
+}
+
+/// Get the world position of the center of a tile
+pub fn tile_center_to_world(tile: TilePoint) -> WorldPoint {
+    tile_to_world(Point2D::new(tile.x as f64 + 0.5, tile.y as f64 + 0.5))
+}
@@ -1,26 +1,17 @@
-use derivative::Derivative;
 use engine::{
     animation::AnimationCursor,
-    coords::WorldPoint,
+    coords::{TilePoint, WorldPoint, WorldRect, WorldSize},
     resources::sprite_map::{SpriteMapAnimation, SpriteMapIdMarker},
-    tile_map::TileMap,
+    tile_map::collision::Contacts,
     types::{Id, Reset},
 };
 use heapless::Vec;
 use sdl3::pixels::Color;
 
-use crate::ecs::MAX_ENTITIES;
+use crate::ecs::{EntityId, MAX_ENTITIES};
 
 pub type Pos = WorldPoint;
 
-#[derive(Clone, Default, Debug)]
-pub struct Tile(pub bool);
-
-#[derive(Clone, Default, Debug)]
-pub struct Terrain {
-    pub tiles: TileMap<Tile>,
-}
-
 pub const MAX_ANIM_PER_ENTITY: usize = 4;
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -45,7 +36,59 @@ pub type SpriteAnims = Vec<SpriteAnim, MAX_ANIM_PER_ENTITY>;
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Follow {
     pub stop_after_arriving: bool,
-    pub target_entity: usize,
+    pub target_entity: EntityId,
+}
+
+/// A box around an entity's position that can't go through solid terrain
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Collider {
+    /// The size of the box, which is centered on the entity's position
+    pub size: WorldSize,
+    /// The solid tiles that stopped the entity during its last move
+    pub contacts: Contacts,
+}
+
+impl Collider {
+    pub fn new(size: WorldSize) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// The box around an entity at `pos`
+    pub fn rect(&self, pos: WorldPoint) -> WorldRect {
+        WorldRect::new(pos - self.size.to_vector() / 2.0, self.size)
+    }
+}
+
+/// The max number of tiles in a path, after which it gets found again
+pub const MAX_PATH_LEN: usize = 64;
+
+/// The max number of entities with a path at a time.
+///
+/// Paths hold their tiles inline, so they are too heavy to give one to every entity
+/// that can follow another. The followers of the targets past this many get steered by
+/// a flow field instead, like crowds are
+pub const MAX_PATHS: usize = 256;
+
+/// The tiles to walk through to reach a `Follow` target without going through terrain
+#[derive(Clone, Default, Debug)]
+pub struct Path {
+    /// The tile of the target when the path was found
+    pub goal: TilePoint,
+    /// The `Terrain::walk_revision` the path was found on
+    pub revision: u64,
+    pub tiles: Vec<TilePoint, MAX_PATH_LEN>,
+    /// The index of the next tile to walk to
+    pub next: usize,
+}
+
+impl Path {
+    /// Whether the path got cut short by `MAX_PATH_LEN` and has been walked to its end
+    pub fn needs_extending(&self) -> bool {
+        self.next >= self.tiles.len() && self.tiles.last().is_some_and(|t| *t != self.goal)
+    }
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -61,11 +104,13 @@ macro_rules! with_components {
     ($inner_macro:ident) => {
         $inner_macro! {
             (pos, $crate::ecs::components::Pos, true, MAX_ENTITIES),
-            (follow, $crate::ecs::components::Follow, true, 64),
+            (follow, $crate::ecs::components::Follow, true, MAX_ENTITIES),
+            (collider, $crate::ecs::components::Collider, true, MAX_ENTITIES),
+            // one more for the sentinel
+            (path, $crate::ecs::components::Path, false, { $crate::ecs::components::MAX_PATHS + 1 }),
             // FIXME: remove debug flags in prod build
             (debug, $crate::ecs::components::DebugFlags, true, MAX_ENTITIES),
-            (sprite_anims, $crate::ecs::components::SpriteAnims, false, MAX_ENTITIES),
-            (terrain, $crate::ecs::components::Terrain, false, 2)
+            (sprite_anims, $crate::ecs::components::SpriteAnims, false, MAX_ENTITIES)
         }
     };
 }
@@ -80,6 +125,9 @@ macro_rules! impl_entity {
         /// attached to it.
         #[derive(Copy, Clone, Default, Debug)]
         pub struct Entity {
+            /// How many times an entity with this index was despawned, which tells
+            /// its `EntityId` apart from the ones of the entities before it
+            pub generation: u16,
             $(
                 pub $attr: usize,
             )*
@@ -96,14 +144,33 @@ macro_rules! impl_components {
         ///
         /// The 0th value of every vec is a sentinel value that should not
         /// be used
-        #[derive(Derivative, Debug)]
-        #[derivative(Clone(clone_from="true"))]
+        #[derive(Debug)]
         pub struct Components {
             $(
-                pub $attr: Vec<(usize, $type), $max>,
+                pub $attr: Vec<(EntityId, $type), $max>,
             )*
         }
 
+        impl Clone for Components {
+            fn clone(&self) -> Self {
+                Self {
+                    $(
+                        $attr: self.$attr.clone(),
+                    )*
+                }
+            }
+
+            /// Copy the lists in place, as the largest ones don't fit on the stack
+            fn clone_from(&mut self, source: &Self) {
+                $(
+                    self.$attr.clear();
+                    self.$attr
+                        .extend_from_slice(&source.$attr)
+                        .unwrap_or_else(|_| panic!("Programming error."));
+                )*
+            }
+        }
+
         impl<'res> Components {
             pub fn new() -> Self {
                 Self {
@@ -2,7 +2,7 @@ use crate::{Ctx, with_components};
 use allocator_api2::alloc::Allocator;
 use anyhow::Result;
 use derivative::Derivative;
-use engine::types::Reset;
+use engine::types::{Id, Reset};
 use heapless::Vec;
 use paste::paste;
 use std::{iter::Iterator, marker::PhantomData};
@@ -10,15 +10,35 @@ use std::{iter::Iterator, marker::PhantomData};
 pub mod components;
 pub use components::Entity;
 
+pub mod query;
+
 pub mod systems;
 use systems::SystemFn;
 
-/// The sentinel value used to represent an entity not having a component or the null entity
+/// The sentinel value used to represent an entity not having a component
 pub const SENTINEL: usize = 0;
 
+/// An entity's index in the ECS in the low bits, and how many times an entity with
+/// that index was despawned in the high bits.
+///
+/// When an index is reused, the IDs of the entities that had it before don't point to
+/// the new entity: its components can't be read with them anymore. An index is retired
+/// instead of reused once its generation runs out, so old IDs never become valid again.
+pub type EntityId = Id<Entity>;
+
+/// The ID of the null entity, which is never in the world
+pub const NULL_ENTITY: EntityId = EntityId::new(0);
+
 /// The max number of entities in the world at a time
 const MAX_ENTITIES: usize = 8192;
 
+// entity indices are stored in the low half of their IDs
+const _: () = assert!(MAX_ENTITIES <= u16::MAX as usize + 1);
+
+/// The generation of an index that had an entity with every other generation, which
+/// is never given to an entity
+const RETIRED_GENERATION: u16 = u16::MAX;
+
 /// Holds all the entities, components and systems of the ECS.
 ///
 /// INVARIANTS:
@@ -31,44 +51,66 @@ pub struct Ecs<A: Allocator + Clone> {
     _pd: PhantomData<A>,
     components: components::Components,
     entities: Vec<Entity, MAX_ENTITIES>,
+    /// The indices of despawned entities, which are reused by the next spawned entities
+    free: Vec<usize, MAX_ENTITIES>,
+    /// How many indices have a `RETIRED_GENERATION` and are never reused
+    retired: usize,
 }
 
 #[cfg(debug_assertions)]
-const NUM_SYSTEMS: usize = 4;
+const NUM_SYSTEMS: usize = 8;
 #[cfg(not(debug_assertions))]
-const NUM_SYSTEMS: usize = 3;
+const NUM_SYSTEMS: usize = 7;
 
 impl<A: Allocator + Clone> Ecs<A> {
     /// All the registered ECS systems
     ///
     /// They execute in order from top to bottom
     const SYSTEMS: [SystemFn<A>; NUM_SYSTEMS] = [
+        systems::navigation::flow::update_and_render,
+        systems::navigation::path::update_and_render,
         systems::navigation::follow::update_and_render,
+        systems::physics::update_and_render,
         systems::draw::update_and_render_terrain,
         systems::draw::update_and_render_animations,
+        systems::draw::update_and_render_terrain_overlay,
         #[cfg(debug_assertions)]
         systems::debug::draw::update_and_render,
     ];
 
-    fn get_component<T: Copy>(components: &[(usize, T)], idx: usize) -> Option<T> {
-        match idx {
-            SENTINEL => None,
-            _ => Some(components[idx].1),
+    /// Get the index of an entity, or `None` if it was despawned since its ID was given
+    /// out or if it's the null entity
+    fn index_of(&self, entity_id: EntityId) -> Option<usize> {
+        let index = entity_id.lo() as usize;
+        if index == NULL_ENTITY.lo() as usize {
+            return None;
         }
+
+        self.entities
+            .get(index)
+            .filter(|entity| {
+                entity.generation == entity_id.hi() && entity.generation != RETIRED_GENERATION
+            })
+            .map(|_| index)
     }
 
-    fn get_component_ref<T>(components: &[(usize, T)], idx: usize) -> Option<&T> {
-        match idx {
-            SENTINEL => None,
-            _ => Some(&components[idx].1),
-        }
+    /// Get the index of an entity that is expected to still be in the world, which
+    /// panics otherwise rather than use the entity that took its index
+    fn index_of_alive(&self, entity_id: EntityId) -> usize {
+        self.index_of(entity_id)
+            .expect("Tried to use an entity that was despawned.")
     }
 
-    fn get_component_mut<T>(components: &mut [(usize, T)], idx: usize) -> Option<&mut T> {
-        match idx {
-            SENTINEL => None,
-            _ => Some(&mut components[idx].1),
-        }
+    /// The number of entities in the world
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        // the null entity isn't in the world
+        self.entities.len() - 1 - self.free.len() - self.retired
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn update_and_render<'gs>(&mut self, ctx: &mut Ctx<'gs, A>, prev: &Ecs<A>) -> Result<()> {
@@ -79,10 +121,33 @@ impl<A: Allocator + Clone> Ecs<A> {
     }
 }
 
+fn get_component<T: Copy>(components: &[(EntityId, T)], idx: usize) -> Option<T> {
+    match idx {
+        SENTINEL => None,
+        _ => Some(components[idx].1),
+    }
+}
+
+fn get_component_ref<T>(components: &[(EntityId, T)], idx: usize) -> Option<&T> {
+    match idx {
+        SENTINEL => None,
+        _ => Some(&components[idx].1),
+    }
+}
+
+fn get_component_mut<T>(components: &mut [(EntityId, T)], idx: usize) -> Option<&mut T> {
+    match idx {
+        SENTINEL => None,
+        _ => Some(&mut components[idx].1),
+    }
+}
+
 impl<A: Allocator + Clone> Reset for Ecs<A> {
     fn reset(&mut self) {
         self.components.reset();
         self.entities.resize(1, Default::default()).unwrap();
+        self.free.clear();
+        self.retired = 0;
     }
 }
 
@@ -91,18 +156,16 @@ macro_rules! impl_accessor_copy {
     ($attr:ident, $type:ty, true, $max:tt) => {
         paste! {
             #[allow(dead_code)]
-            pub fn [<$attr _for>](&self, entity_id: usize) -> Option<$type> {
-                debug_assert!(entity_id != SENTINEL);
-
-                let attr_idx = self.entities[entity_id].$attr;
-                Self::get_component(&self.components.$attr, attr_idx)
+            pub fn [<$attr _for>](&self, entity_id: EntityId) -> Option<$type> {
+                let attr_idx = self.entities[self.index_of(entity_id)?].$attr;
+                get_component(&self.components.$attr, attr_idx)
             }
 
             #[allow(dead_code)]
-            pub fn [<$attr _for_unchecked>](&self, entity_id: usize) -> $type {
-                debug_assert!(entity_id != SENTINEL);
+            pub fn [<$attr _for_unchecked>](&self, entity_id: EntityId) -> $type {
+                debug_assert!(entity_id != NULL_ENTITY);
 
-                let attr_idx = self.entities[entity_id].$attr;
+                let attr_idx = self.entities[self.index_of_alive(entity_id)].$attr;
                 debug_assert!(attr_idx != SENTINEL, concat!("Tried to get '",stringify!($attr),"' attribute from entity that does not contain it."));
                 self.components.$attr[attr_idx].1
             }
@@ -113,18 +176,16 @@ macro_rules! impl_accessor_copy {
     ($attr:ident, $type:ty, false, $max:tt) => {
         paste! {
             #[allow(dead_code)]
-            pub fn [<$attr _for>](&self, entity_id: usize) -> Option<&$type> {
-                debug_assert!(entity_id != SENTINEL);
-
-                let attr_idx = self.entities[entity_id].$attr;
-                Self::get_component_ref(&self.components.$attr, attr_idx)
+            pub fn [<$attr _for>](&self, entity_id: EntityId) -> Option<&$type> {
+                let attr_idx = self.entities[self.index_of(entity_id)?].$attr;
+                get_component_ref(&self.components.$attr, attr_idx)
             }
 
             #[allow(dead_code)]
-            pub fn [<$attr _for_unchecked>](&self, entity_id: usize) -> &$type {
-                debug_assert!(entity_id != SENTINEL);
+            pub fn [<$attr _for_unchecked>](&self, entity_id: EntityId) -> &$type {
+                debug_assert!(entity_id != NULL_ENTITY);
 
-                let attr_idx = self.entities[entity_id].$attr;
+                let attr_idx = self.entities[self.index_of_alive(entity_id)].$attr;
                 debug_assert!(attr_idx != SENTINEL, concat!("Tried to get '",stringify!($attr),"' attribute from entity that does not contain it."));
                 &self.components.$attr[attr_idx].1
             }
@@ -136,8 +197,8 @@ macro_rules! impl_accessor_copy {
 macro_rules! impl_accessor {
     ($attr:ident, $type:ty, $cheap_copy:tt, $max:tt) => {
         paste! {
-            fn [<push_ $attr _unchecked>]<const N: usize>(components: &mut Vec<(usize, $type), N>, entity_id: usize, entity: &mut Entity, value: $type) {
-                debug_assert!(entity_id != SENTINEL);
+            fn [<push_ $attr _unchecked>]<const N: usize>(components: &mut Vec<(EntityId, $type), N>, entity_id: EntityId, entity: &mut Entity, value: $type) {
+                debug_assert!(entity_id != NULL_ENTITY);
 
                 let component_id = components.len();
                 components.push((entity_id, value)).expect("Too many components.");
@@ -145,37 +206,37 @@ macro_rules! impl_accessor {
             }
 
             #[allow(dead_code)]
-            pub fn [<$attr _for_mut>](&mut self, entity_id: usize) -> Option<&mut $type> {
-                debug_assert!(entity_id != SENTINEL);
-
-                let attr_idx = self.entities[entity_id].$attr;
-                Self::get_component_mut(&mut self.components.$attr, attr_idx)
+            pub fn [<$attr _for_mut>](&mut self, entity_id: EntityId) -> Option<&mut $type> {
+                let attr_idx = self.entities[self.index_of(entity_id)?].$attr;
+                get_component_mut(&mut self.components.$attr, attr_idx)
             }
 
             #[allow(dead_code)]
-            pub fn [<$attr _for_mut_unchecked>](&mut self, entity_id: usize) -> &mut $type {
-                debug_assert!(entity_id != SENTINEL);
+            pub fn [<$attr _for_mut_unchecked>](&mut self, entity_id: EntityId) -> &mut $type {
+                debug_assert!(entity_id != NULL_ENTITY);
 
-                let attr_idx = self.entities[entity_id].$attr;
+                let attr_idx = self.entities[self.index_of_alive(entity_id)].$attr;
                 debug_assert!(attr_idx != SENTINEL, concat!("Tried to get mut '",stringify!($attr),"' in entity that does not contain it."));
                 &mut self.components.$attr[attr_idx].1
             }
 
+            /// Does nothing if the entity was despawned
             #[allow(dead_code)]
-            pub fn [<set_ $attr _for>](&mut self, entity_id: usize, val: $type) {
-                debug_assert!(entity_id != SENTINEL);
-
-                let attr_idx = self.entities[entity_id].$attr;
+            pub fn [<set_ $attr _for>](&mut self, entity_id: EntityId, val: $type) {
+                let Some(index) = self.index_of(entity_id) else {
+                    return;
+                };
+                let attr_idx = self.entities[index].$attr;
                 debug_assert!(attr_idx != SENTINEL, concat!("Tried to set '",stringify!($attr),"' in entity that does not contain it."));
                 self.components.$attr[attr_idx].1 = val;
             }
 
+            /// Returns `None` if the entity was despawned
             #[allow(dead_code)]
-            pub fn [<unset_ $attr _for>](&mut self, entity_id: usize) -> $type {
-                debug_assert!(entity_id != SENTINEL);
-
+            pub fn [<unset_ $attr _for>](&mut self, entity_id: EntityId) -> Option<$type> {
                 let attr_idx = {
-                    let entity = &mut self.entities[entity_id];
+                    let index = self.index_of(entity_id)?;
+                    let entity = &mut self.entities[index];
                     let attr_idx = entity.$attr;
                     debug_assert!(attr_idx != SENTINEL, concat!("Tried to unset '",stringify!($attr),"' but it's already unset."));
 
@@ -189,20 +250,22 @@ macro_rules! impl_accessor {
                 // rewire indexes in the other entity that got swapped if it was not the last
                 if attr_idx < self.components.$attr.len() {
                     let (swapped_entity_id, _) = self.components.$attr[attr_idx];
-                    self.entities[swapped_entity_id].$attr = attr_idx;
+                    self.entities[swapped_entity_id.lo() as usize].$attr = attr_idx;
                 }
 
-                removed
+                Some(removed)
             }
 
+            /// Does nothing if the entity was despawned
             #[allow(dead_code)]
-            pub fn [<overwrite_ $attr _for>](&mut self, entity_id: usize, val: $type) {
-                debug_assert!(entity_id != SENTINEL);
-
-                let attr_idx = self.entities[entity_id].$attr;
+            pub fn [<overwrite_ $attr _for>](&mut self, entity_id: EntityId, val: $type) {
+                let Some(index) = self.index_of(entity_id) else {
+                    return;
+                };
+                let attr_idx = self.entities[index].$attr;
                 match attr_idx {
                     SENTINEL => {
-                        Self::[<push_ $attr _unchecked>](&mut self.components.$attr, entity_id, &mut self.entities[entity_id], val);
+                        Self::[<push_ $attr _unchecked>](&mut self.components.$attr, entity_id, &mut self.entities[index], val);
                     }
                     _ => {
                         self.components.$attr[attr_idx].1 = val;
@@ -211,7 +274,7 @@ macro_rules! impl_accessor {
             }
 
             #[allow(dead_code)]
-            pub fn [<$attr _iter>](&self) -> impl Iterator<Item = &(usize, $type)> {
+            pub fn [<$attr _iter>](&self) -> impl Iterator<Item = &(EntityId, $type)> {
                 // skip the sentinel
                 self.components.$attr.iter().skip(1)
             }
@@ -233,6 +296,46 @@ impl<A: Allocator + Clone> Ecs<A> {
     with_components!(impl_accessors);
 }
 
+/// Implement removing entities with any of the components
+macro_rules! impl_despawn {
+    ( $( ($attr:ident, $type:ty, $cheap_copy:tt, $max:tt) ),+ ) => {
+        impl<A: Allocator + Clone> Ecs<A> {
+            /// Remove an entity with all of its components.
+            ///
+            /// Its index is given to the next spawned entity, but its ID becomes stale
+            /// and doesn't get the components of the new entity. Returns whether the
+            /// entity was still in the world, which the null entity never is.
+            #[allow(dead_code)]
+            pub fn despawn(&mut self, entity_id: EntityId) -> bool {
+                let Some(index) = self.index_of(entity_id) else {
+                    return false;
+                };
+
+                $(
+                    if self.entities[index].$attr != SENTINEL {
+                        paste! {
+                            self.[<unset_ $attr _for>](entity_id);
+                        }
+                    }
+                )*
+
+                let entity = &mut self.entities[index];
+                entity.generation += 1;
+                if entity.generation == RETIRED_GENERATION {
+                    // every generation was given out, so a new one would match a stale ID
+                    self.retired += 1;
+                } else {
+                    // there are never more despawned entities than entities
+                    self.free.push(index).unwrap();
+                }
+                true
+            }
+        }
+    };
+}
+
+with_components!(impl_despawn);
+
 /// Impleent the entity spawner with all possible components
 macro_rules! impl_entity_spawner {
     ( $( ($attr:ident, $type:ty, $cheap_copy:tt, $max:tt) ),+ ) => {
@@ -269,12 +372,20 @@ macro_rules! impl_entity_spawner {
             }
 
             /// Spawn the entity into the ECS world
-            pub fn spawn<A: Allocator + Clone>(self, ecs: &mut Ecs<A>) -> usize {
-                let entity_id = ecs.entities.len();
-                // FIXME: what to do when there are too many entities that get spawned? Fail
-                // silently?
-                ecs.entities.push(Default::default()).unwrap_or_else(|_| panic!("Too many entities"));
-                let entity = &mut ecs.entities[entity_id];
+            pub fn spawn<A: Allocator + Clone>(self, ecs: &mut Ecs<A>) -> EntityId {
+                // reuse the indices of despawned entities first, which have no components
+                // left
+                let index = match ecs.free.pop() {
+                    Some(index) => index,
+                    None => {
+                        // FIXME: what to do when there are too many entities that get
+                        // spawned? Fail silently?
+                        ecs.entities.push(Default::default()).unwrap_or_else(|_| panic!("Too many entities"));
+                        ecs.entities.len() - 1
+                    }
+                };
+                let entity = &mut ecs.entities[index];
+                let entity_id = EntityId::new_split(entity.generation, index as u16);
 
                 $(
                     if let Some(value) = self.$attr {
@@ -291,3 +402,189 @@ macro_rules! impl_entity_spawner {
 }
 
 with_components!(impl_entity_spawner);
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ops::{Deref, DerefMut},
+        ptr::NonNull,
+    };
+
+    use allocator_api2::alloc::{Global, Layout};
+    use engine::coords::WorldPoint;
+
+    use super::*;
+    use crate::ecs::components::Follow;
+
+    /// An ECS on the heap, as it's too large for the stack of a test thread
+    pub(crate) struct HeapEcs(NonNull<Ecs<Global>>);
+
+    impl HeapEcs {
+        pub fn new() -> Self {
+            let layout = Layout::new::<Ecs<Global>>();
+            let ptr = Global.allocate_zeroed(layout).unwrap().cast();
+            let mut ecs = Self(ptr);
+            // like in `init`, the zeroed memory only has to be reset
+            ecs.reset();
+            ecs
+        }
+    }
+
+    impl Deref for HeapEcs {
+        type Target = Ecs<Global>;
+
+        fn deref(&self) -> &Self::Target {
+            // SAFETY: the memory is owned by this and was initialized in `new`
+            unsafe { self.0.as_ref() }
+        }
+    }
+
+    impl DerefMut for HeapEcs {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            // SAFETY: the memory is owned by this and was initialized in `new`
+            unsafe { self.0.as_mut() }
+        }
+    }
+
+    impl Drop for HeapEcs {
+        fn drop(&mut self) {
+            // SAFETY: the memory is owned by this and was initialized in `new`
+            unsafe {
+                std::ptr::drop_in_place(self.0.as_ptr());
+                Global.deallocate(self.0.cast(), Layout::new::<Ecs<Global>>());
+            }
+        }
+    }
+
+    fn spawn_at(ecs: &mut Ecs<Global>, x: f64) -> EntityId {
+        EntitySpawner::new()
+            .with_pos(WorldPoint::new(x, 0.0))
+            .spawn(ecs)
+    }
+
+    #[test]
+    fn test_despawn_reuses_slots() {
+        let mut ecs = HeapEcs::new();
+        let a = spawn_at(&mut ecs, 1.0);
+        let b = spawn_at(&mut ecs, 2.0);
+        let c = spawn_at(&mut ecs, 3.0);
+        assert_eq!(ecs.len(), 3);
+
+        assert!(ecs.despawn(a));
+        assert!(ecs.despawn(b));
+        assert_eq!(ecs.len(), 1);
+
+        // the last despawned slot is reused first, without growing the entity list
+        let d = spawn_at(&mut ecs, 4.0);
+        assert_eq!(d.lo(), b.lo());
+        let e = spawn_at(&mut ecs, 5.0);
+        assert_eq!(e.lo(), a.lo());
+        assert_eq!(ecs.len(), 3);
+        assert_eq!(ecs.entities.len(), 4);
+
+        let f = spawn_at(&mut ecs, 6.0);
+        assert_eq!(f.lo(), c.lo() + 1);
+        assert_eq!(ecs.len(), 4);
+    }
+
+    #[test]
+    fn test_despawn_null_entity() {
+        let mut ecs = HeapEcs::new();
+        let a = spawn_at(&mut ecs, 1.0);
+
+        assert!(!ecs.despawn(NULL_ENTITY));
+        assert!(ecs.free.is_empty());
+        assert!(ecs.pos_for(NULL_ENTITY).is_none());
+
+        // the null entity's index is never given out
+        let b = spawn_at(&mut ecs, 2.0);
+        assert_ne!(b.lo(), NULL_ENTITY.lo());
+        assert_eq!(b.lo(), a.lo() + 1);
+    }
+
+    #[test]
+    fn test_despawn_releases_components() {
+        let mut ecs = HeapEcs::new();
+        let a = EntitySpawner::new()
+            .with_pos(WorldPoint::new(1.0, 0.0))
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+        let b = spawn_at(&mut ecs, 2.0);
+
+        ecs.despawn(a);
+        assert_eq!(ecs.pos_iter().count(), 1);
+        assert_eq!(ecs.follow_iter().count(), 0);
+        assert_eq!(ecs.free.len(), 1);
+
+        // the swapped component of the other entity is still found
+        assert_eq!(ecs.pos_for(b), Some(WorldPoint::new(2.0, 0.0)));
+
+        // a reused slot starts without the components of the despawned entity
+        let c = EntitySpawner::new().spawn(&mut ecs);
+        assert_eq!(c.lo(), a.lo());
+        assert_eq!(ecs.pos_for(c), None);
+        assert!(ecs.follow_for(c).is_none());
+        assert!(ecs.free.is_empty());
+    }
+
+    #[test]
+    fn test_generation_bumps_on_reuse() {
+        let mut ecs = HeapEcs::new();
+        let old = spawn_at(&mut ecs, 1.0);
+        assert!(ecs.despawn(old));
+
+        let new = spawn_at(&mut ecs, 2.0);
+        assert_eq!(new.lo(), old.lo());
+        assert_eq!(new.hi(), old.hi() + 1);
+        assert_ne!(new, old);
+    }
+
+    #[test]
+    fn test_generation_retires_before_wrapping() {
+        let mut ecs = HeapEcs::new();
+        let first = EntitySpawner::new().spawn(&mut ecs);
+
+        // as if the index already had an entity of every generation before the last one
+        let index = first.lo() as usize;
+        ecs.entities[index].generation = RETIRED_GENERATION - 1;
+        let last = EntityId::new_split(RETIRED_GENERATION - 1, first.lo());
+        assert!(ecs.despawn(last));
+        assert!(ecs.free.is_empty());
+        assert_eq!(ecs.len(), 0);
+
+        // neither the last ID nor the first one that a wrapped generation would match
+        assert!(!ecs.despawn(last));
+        assert!(!ecs.despawn(first));
+        let other = EntitySpawner::new().spawn(&mut ecs);
+        assert_ne!(other.lo(), first.lo());
+        assert_eq!(ecs.len(), 1);
+    }
+
+    #[test]
+    fn test_stale_id_reads_nothing() {
+        let mut ecs = HeapEcs::new();
+        let old = spawn_at(&mut ecs, 1.0);
+        ecs.despawn(old);
+        let new = spawn_at(&mut ecs, 2.0);
+
+        assert_eq!(ecs.pos_for(old), None);
+        assert_eq!(ecs.pos_for_mut(old), None);
+        assert_eq!(ecs.pos_for(new), Some(WorldPoint::new(2.0, 0.0)));
+    }
+
+    #[test]
+    fn test_stale_id_writes_nothing() {
+        let mut ecs = HeapEcs::new();
+        let old = spawn_at(&mut ecs, 1.0);
+        ecs.despawn(old);
+        let new = spawn_at(&mut ecs, 2.0);
+
+        ecs.set_pos_for(old, WorldPoint::new(3.0, 0.0));
+        ecs.overwrite_follow_for(old, Follow::default());
+        assert_eq!(ecs.unset_pos_for(old), None);
+        assert!(!ecs.despawn(old));
+
+        assert_eq!(ecs.pos_for(new), Some(WorldPoint::new(2.0, 0.0)));
+        assert!(ecs.follow_for(new).is_none());
+    }
+}
@@ -0,0 +1,253 @@
+//! Iterating over the entities that have a combination of components
+
+use allocator_api2::alloc::Allocator;
+use paste::paste;
+
+use super::{Ecs, Entity, EntityId, components::Components, get_component, get_component_ref};
+use crate::with_components;
+
+impl<A: Allocator + Clone> Ecs<A> {
+    /// The entities and their component lists, which `query!` borrows one by one
+    pub fn query_parts(&self) -> (&[Entity], &Components) {
+        (&self.entities, &self.components)
+    }
+
+    /// The entities and their component lists, which `query!` borrows one by one so
+    /// one of them can be mutable
+    #[allow(dead_code)]
+    pub fn query_parts_mut(&mut self) -> (&[Entity], &mut Components) {
+        (&self.entities, &mut self.components)
+    }
+}
+
+/// Helper to get a component the way a query yields it
+macro_rules! impl_fetch {
+    // cheap_copy
+    ($attr:ident, $type:ty, true, $max:tt) => {
+        paste! {
+            #[allow(dead_code)]
+            pub fn [<fetch_ $attr>](components: &[(EntityId, $type)], idx: usize) -> Option<$type> {
+                get_component(components, idx)
+            }
+        }
+    };
+
+    // expensive copy
+    ($attr:ident, $type:ty, false, $max:tt) => {
+        paste! {
+            #[allow(dead_code)]
+            pub fn [<fetch_ $attr>](components: &[(EntityId, $type)], idx: usize) -> Option<&$type> {
+                get_component_ref(components, idx)
+            }
+        }
+    };
+}
+
+/// Implement the fetch helpers for all the component types
+macro_rules! impl_fetches {
+    ( $( ($attr:ident, $type:ty, $cheap_copy:tt, $max:tt) ),+ ) => {
+        $(
+            impl_fetch!($attr, $type, $cheap_copy, $max);
+        )*
+    }
+}
+
+impl Components {
+    with_components!(impl_fetches);
+}
+
+/// Iterate over the entities that have all of a list of components.
+///
+/// Yields the entity ID followed by the components in the order they're listed,
+/// then by the optional ones, which are `None` when the entity lacks them. Entities
+/// with any of the excluded components are skipped:
+///
+/// ```ignore
+/// for (entity_id, pos, anims, debug) in query!(prev, [pos, sprite_anims], optional[debug], without[follow]) {}
+/// ```
+///
+/// The first component can be borrowed mutably with `[mut follow, pos]`, then the
+/// iteration follows its list. Only that one can be mutable: the other components
+/// of each entity are looked up in their lists while it's borrowed, which the
+/// iterator can't hand out mutably without unsafe code proving that no two items
+/// borrow the same component. Cheap components are yielded by value and the others
+/// by reference, like `*_for`.
+#[macro_export]
+macro_rules! query {
+    (
+        $ecs:expr, [mut $first:ident $(, $attr:ident)* $(,)?]
+        $(, optional [$($opt:ident),* $(,)?])?
+        $(, without [$($excluded:ident),* $(,)?])? $(,)?
+    ) => {{
+        let (entities, components) = $ecs.query_parts_mut();
+        // each list is borrowed on its own, so they can be borrowed along with the
+        // mutable one
+        $( let $attr = &components.$attr; )*
+        $($( let $opt = &components.$opt; )*)?
+
+        components.$first.iter_mut().skip(1).filter_map(move |(entity_id, $first)| {
+            let entity = &entities[entity_id.lo() as usize];
+            $($(
+                if entity.$excluded != $crate::ecs::SENTINEL {
+                    return None;
+                }
+            )*)?
+
+            paste::paste! {
+                Some((
+                    *entity_id,
+                    $first,
+                    $( $crate::ecs::components::Components::[<fetch_ $attr>]($attr, entity.$attr)?, )*
+                    $($( $crate::ecs::components::Components::[<fetch_ $opt>]($opt, entity.$opt), )*)?
+                ))
+            }
+        })
+    }};
+
+    (
+        $ecs:expr, [$first:ident $(, $attr:ident)* $(,)?]
+        $(, optional [$($opt:ident),* $(,)?])?
+        $(, without [$($excluded:ident),* $(,)?])? $(,)?
+    ) => {{
+        let (entities, components) = $ecs.query_parts();
+        let $first = &components.$first;
+        $( let $attr = &components.$attr; )*
+        $($( let $opt = &components.$opt; )*)?
+
+        // skip the sentinel
+        $first.iter().skip(1).filter_map(move |(entity_id, _)| {
+            let entity = &entities[entity_id.lo() as usize];
+            $($(
+                if entity.$excluded != $crate::ecs::SENTINEL {
+                    return None;
+                }
+            )*)?
+
+            paste::paste! {
+                Some((
+                    *entity_id,
+                    $crate::ecs::components::Components::[<fetch_ $first>]($first, entity.$first)?,
+                    $( $crate::ecs::components::Components::[<fetch_ $attr>]($attr, entity.$attr)?, )*
+                    $($( $crate::ecs::components::Components::[<fetch_ $opt>]($opt, entity.$opt), )*)?
+                ))
+            }
+        })
+    }};
+
+    ($($tokens:tt)*) => {
+        compile_error!(
+            "Expected `query!(ecs, [mut a, b], optional[c], without[d])`, where only the first component can be `mut`."
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use engine::coords::WorldPoint;
+    use sdl3::pixels::Color;
+
+    use crate::ecs::{
+        EntitySpawner,
+        components::{DebugFlags, Follow, Path},
+        tests::HeapEcs,
+    };
+
+    fn at(x: f64) -> WorldPoint {
+        WorldPoint::new(x, 0.0)
+    }
+
+    #[test]
+    fn test_required_components() {
+        let mut ecs = HeapEcs::new();
+        let both = EntitySpawner::new()
+            .with_pos(at(1.0))
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+        EntitySpawner::new().with_pos(at(2.0)).spawn(&mut ecs);
+        EntitySpawner::new()
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+
+        let found: heapless::Vec<_, 4> = query!(ecs, [follow, pos])
+            .map(|(entity_id, _, pos)| (entity_id, pos))
+            .collect();
+        assert_eq!(found.as_slice(), &[(both, at(1.0))]);
+    }
+
+    #[test]
+    fn test_optional_and_excluded_components() {
+        let mut ecs = HeapEcs::new();
+        let debug = DebugFlags {
+            box_color: Some(Color::RED),
+        };
+        EntitySpawner::new()
+            .with_pos(at(1.0))
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+        let with_debug = EntitySpawner::new()
+            .with_pos(at(2.0))
+            .with_debug(debug)
+            .spawn(&mut ecs);
+        let without_debug = EntitySpawner::new().with_pos(at(3.0)).spawn(&mut ecs);
+
+        let found: heapless::Vec<_, 4> = query!(ecs, [pos], optional[debug], without[follow])
+            .map(|(entity_id, pos, debug)| (entity_id, pos, debug.and_then(|d| d.box_color)))
+            .collect();
+        assert_eq!(
+            found.as_slice(),
+            &[
+                (with_debug, at(2.0), Some(Color::RED)),
+                (without_debug, at(3.0), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expensive_components_by_reference() {
+        let mut ecs = HeapEcs::new();
+        let path = Path {
+            revision: 1,
+            ..Default::default()
+        };
+        let entity_id = EntitySpawner::new()
+            .with_pos(at(1.0))
+            .with_path(path)
+            .spawn(&mut ecs);
+
+        let (found_id, pos, path): (_, WorldPoint, &Path) =
+            query!(ecs, [pos, path]).next().unwrap();
+        assert_eq!((found_id, pos), (entity_id, at(1.0)));
+        assert_eq!(path.revision, 1);
+    }
+
+    #[test]
+    fn test_mutable_component() {
+        let mut ecs = HeapEcs::new();
+        let moving = EntitySpawner::new()
+            .with_pos(at(1.0))
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+        let excluded = EntitySpawner::new()
+            .with_pos(at(2.0))
+            .with_follow(Follow::default())
+            .with_debug(DebugFlags::default())
+            .spawn(&mut ecs);
+        let without_pos = EntitySpawner::new()
+            .with_follow(Follow::default())
+            .spawn(&mut ecs);
+
+        let mut count = 0;
+        for (_, follow, pos, path) in query!(ecs, [mut follow, pos], optional[path], without[debug])
+        {
+            assert_eq!(pos, at(1.0));
+            assert!(path.is_none());
+            follow.stop_after_arriving = true;
+            count += 1;
+        }
+        assert_eq!(count, 1);
+
+        assert!(ecs.follow_for(moving).unwrap().stop_after_arriving);
+        assert!(!ecs.follow_for(excluded).unwrap().stop_after_arriving);
+        assert!(!ecs.follow_for(without_pos).unwrap().stop_after_arriving);
+    }
+}
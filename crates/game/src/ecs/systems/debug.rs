@@ -1,6 +1,6 @@
 //! Debugging utilities
 
-use crate::{Ctx, ecs::Ecs};
+use crate::{Ctx, ecs::Ecs, query};
 
 /// System to draw debug squares around entities
 pub mod draw {
@@ -8,17 +8,14 @@ pub mod draw {
     use engine::coords::{self, WorldRect, WorldSize};
 
     use super::*;
+    use crate::coords::{WORLD_TO_TILE, tile_to_world};
 
     pub fn update_and_render<'gs, A: Allocator + Clone>(
         ctx: &mut Ctx<'gs, A>,
         prev: &Ecs<A>,
         _next: &mut Ecs<A>,
     ) -> anyhow::Result<()> {
-        for &(entity_id, pos) in prev.pos_iter() {
-            let Some(dbg_flags) = prev.debug_for(entity_id) else {
-                continue;
-            };
-
+        for (_, dbg_flags, pos, collider) in query!(prev, [debug, pos], optional[collider]) {
             let Some(box_color) = dbg_flags.box_color else {
                 continue;
             };
@@ -28,6 +25,22 @@ pub mod draw {
                 &ctx.camera
                     .world_to_screen_rect(&WorldRect::new(pos, WorldSize::new(25.0, 25.0))),
             ))?;
+
+            // the collider and the tiles it's stopped by
+            let Some(collider) = collider else {
+                continue;
+            };
+            let tiles = collider.contacts.iter().map(|contact| {
+                WorldRect::new(
+                    tile_to_world(contact.tile.cast()),
+                    WorldSize::splat(1.0 / WORLD_TO_TILE),
+                )
+            });
+            for rect in [collider.rect(pos)].into_iter().chain(tiles) {
+                ctx.canvas.draw_rect(coords::convert::screen_rect_to_sdl(
+                    &ctx.camera.world_to_screen_rect(&rect),
+                ))?;
+            }
         }
         Ok(())
     }
@@ -1,66 +1,320 @@
 //! Drawing, animation and rendering systems
 
 use allocator_api2::alloc::Allocator;
-use engine::coords::{WorldPoint, WorldRect, WorldSize, convert::screen_rect_to_sdl};
+use engine::{
+    coords::{
+        ScreenPoint, ScreenRect, ScreenSize, TilePoint, TileVector, WorldPoint, WorldRect,
+        WorldSize, convert::screen_rect_to_sdl,
+    },
+    resources::{
+        sprite_map::{ResolvedTileset, SpriteMap, Tileset},
+        tile_kinds::TileKindId,
+    },
+    tile_map::chunked::{CHUNK_SIZE, ChunkedTileMap},
+    types::Id,
+};
+use euclid::Point2D;
+use sdl3::{
+    pixels::Color,
+    render::{FRect, WindowCanvas},
+};
 
-use crate::{Ctx, coords::WORLD_TO_PIXEL, ecs::Ecs};
+use crate::{
+    Ctx,
+    coords::{WORLD_TO_TILE, tile_to_world},
+    ecs::Ecs,
+    query,
+};
 
+/// Terrain layers drawn into one texture per chunk, which are kept between frames
+pub mod cache {
+    use std::mem;
+
+    use allocator_api2::vec::Vec;
+    use engine::coords::ChunkPoint;
+    use hashbrown::{DefaultHashBuilder, HashMap, hash_map::Entry};
+    use sdl3::{
+        pixels::PixelFormatEnum,
+        render::{BlendMode, ScaleMode, Texture, TextureCreator},
+        video::WindowContext,
+    };
+
+    use super::*;
+
+    /// A chunk of a terrain layer drawn into a texture
+    pub(super) struct ChunkTexture<A: Allocator + Clone> {
+        /// Not tied to the `TextureCreator` that made it, as the cache is kept across
+        /// frames and reloads. It's destroyed when dropped, which must happen before
+        /// the renderer is destroyed
+        pub tex: Texture<'static>,
+        /// `ChunkedTileMap::revision_around` the chunk when it was drawn, or `None` if
+        /// it wasn't drawn yet
+        pub revision: Option<u64>,
+        /// The tiles with animated tilesets, which are left out of the texture
+        pub animated: Vec<TilePoint, A>,
+    }
+
+    /// The chunk textures of the terrain layers in view, by layer and chunk.
+    ///
+    /// Textures can't be cloned, so the cache can't be part of the double-buffered
+    /// state and is kept next to the terrain instead. A chunk's
+    /// texture is destroyed when the chunk is evicted, cleared or when the cache is
+    /// dropped.
+    pub(crate) struct TerrainCache<A: Allocator + Clone> {
+        allocator: A,
+        chunks: HashMap<(usize, ChunkPoint), ChunkTexture<A>, DefaultHashBuilder, A>,
+    }
+
+    impl<A: Allocator + Clone> TerrainCache<A> {
+        pub fn new_in(allocator: A) -> Self {
+            Self {
+                chunks: HashMap::new_in(allocator.clone()),
+                allocator,
+            }
+        }
+
+        /// Forget the chunks of a layer that went out of view
+        pub(super) fn evict(&mut self, layer: usize, mut in_view: impl FnMut(ChunkPoint) -> bool) {
+            self.chunks
+                .retain(|&(chunk_layer, chunk), _| chunk_layer != layer || in_view(chunk));
+        }
+
+        /// Forget every chunk, like when the terrain is replaced by another one whose
+        /// revisions could match the cached ones
+        pub fn clear(&mut self) {
+            self.chunks.clear();
+        }
+
+        /// Get the texture of a chunk of a layer, creating an empty one that fits
+        /// `tile_pixels` pixels per tile if there's none yet
+        pub(super) fn get_or_create(
+            &mut self,
+            tc: &TextureCreator<WindowContext>,
+            layer: usize,
+            chunk: ChunkPoint,
+            tile_pixels: u32,
+        ) -> anyhow::Result<&mut ChunkTexture<A>> {
+            let cached = match self.chunks.entry((layer, chunk)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let size = CHUNK_SIZE as u32 * tile_pixels;
+                    let mut tex = tc.create_texture_target(
+                        Some(PixelFormatEnum::RGBA8888.into()),
+                        size,
+                        size,
+                    )?;
+                    tex.set_blend_mode(BlendMode::Blend);
+                    tex.set_scale_mode(ScaleMode::Nearest);
+                    // SAFETY: only the lifetime changes. The game is dropped, and the
+                    // cache with it, before the renderer
+                    let tex = unsafe { mem::transmute::<Texture<'_>, Texture<'static>>(tex) };
+
+                    entry.insert(ChunkTexture {
+                        tex,
+                        revision: None,
+                        animated: Vec::new_in(self.allocator.clone()),
+                    })
+                }
+            };
+            Ok(cached)
+        }
+    }
+}
+
+/// The index of each layer in `Terrain::layers`, which also tells their cached chunks
+/// apart
+const GROUND: usize = 0;
+const WALLS: usize = 1;
+const DECORATION: usize = 2;
+const OVERLAY: usize = 3;
+
+/// Draws the terrain layers below entities: ground, walls and decoration
 pub fn update_and_render_terrain<'gs, A: Allocator + Clone>(
     ctx: &mut Ctx<'gs, A>,
-    prev: &Ecs<A>,
+    _prev: &Ecs<A>,
+    _next: &mut Ecs<A>,
+) -> anyhow::Result<()> {
+    let Some(res) = &ctx.resource_ids.terrain else {
+        return Ok(());
+    };
+    let (ground, walls, decoration) = (res.ground, res.walls, res.decoration);
+
+    for (layer, layer_tileset) in [(GROUND, ground), (WALLS, walls), (DECORATION, decoration)] {
+        render_layer(ctx, layer, layer_tileset)?;
+    }
+
+    Ok(())
+}
+
+/// Draws the terrain layer above entities
+pub fn update_and_render_terrain_overlay<'gs, A: Allocator + Clone>(
+    ctx: &mut Ctx<'gs, A>,
+    _prev: &Ecs<A>,
     _next: &mut Ecs<A>,
 ) -> anyhow::Result<()> {
     let Some(res) = &ctx.resource_ids.terrain else {
         return Ok(());
     };
+    let overlay = res.overlay;
+
+    render_layer(ctx, OVERLAY, overlay)?;
 
+    Ok(())
+}
+
+/// Draws every tile of a layer, connecting it to its neighbors of the same kind.
+///
+/// Chunks are drawn from their cached texture, which is only redrawn when a tile in
+/// the chunk or bordering it changes. Animated tilesets all follow the game clock, so
+/// their tiles stay in sync: they're drawn over the cached chunk on every frame
+fn render_layer<A: Allocator + Clone>(
+    ctx: &mut Ctx<'_, A>,
+    layer: usize,
+    layer_tileset: Id<Tileset>,
+) -> anyhow::Result<()> {
+    let Some(res) = &ctx.resource_ids.terrain else {
+        return Ok(());
+    };
+    let Some(terrain) = ctx.terrain.as_ref() else {
+        return Ok(());
+    };
+    let tiles = terrain.layers()[layer];
     let sprite_map = ctx.resources.sprites.get(res.sprite);
-    let tileset = sprite_map.get_tileset(res.tileset);
+    let tileset_of =
+        |kind: &TileKindId| res.kind_tilesets[kind.0 as usize].unwrap_or(layer_tileset);
 
-    let block_width_world = WORLD_TO_PIXEL / tileset.grid_size as f64;
+    let tile_pixels = sprite_map.get_tileset(layer_tileset).grid_size as u32;
+    let tile_world = 1.0 / WORLD_TO_TILE;
+    let screen = ScreenRect::new(
+        ScreenPoint::origin(),
+        ScreenSize::new(ctx.screen_w as f64, ctx.screen_h as f64),
+    );
+    let tc = ctx.canvas.texture_creator();
+    let camera = &*ctx.camera;
+    let chunk_screen_rect = |chunk| {
+        let origin = ChunkedTileMap::<TileKindId>::chunk_origin(chunk);
+        let world_rect = WorldRect::new(
+            tile_to_world(origin.cast()),
+            WorldSize::splat(CHUNK_SIZE as f64 * tile_world),
+        );
+        camera.world_to_screen_rect(&world_rect)
+    };
 
-    for (_, terrain) in prev.terrain_iter() {
-        // OPTIMIZE: use a pre-computed sprite that gets saved between frames
-        for x in 1..terrain.tiles.size() {
-            for y in 1..terrain.tiles.size() {
-                let tile = terrain.tiles.get(x, y);
-                if !tile.0 {
-                    continue;
-                }
+    // the cache only keeps what's drawn, so it doesn't grow with the whole terrain
+    ctx.terrain_cache
+        .evict(layer, |chunk| chunk_screen_rect(chunk).intersects(&screen));
 
-                let solid_neighbors = terrain.tiles.filter_neighbors(x, y, |t| t.0);
+    for (chunk, _) in tiles.chunks() {
+        let origin = ChunkedTileMap::<TileKindId>::chunk_origin(chunk);
+        let screen_rect = chunk_screen_rect(chunk);
+        if !screen_rect.intersects(&screen) {
+            continue;
+        }
 
-                let tex_rect = tileset.tex_rect_for(solid_neighbors);
+        let cached = ctx
+            .terrain_cache
+            .get_or_create(&tc, layer, chunk, tile_pixels)?;
+        let revision = tiles.revision_around(chunk);
+        if cached.revision != Some(revision) {
+            cached.animated.clear();
 
-                let world_pos = WorldPoint::new(
-                    (x * block_width_world as usize) as f64,
-                    (y * block_width_world as usize) as f64,
-                );
-                let world_size = WorldSize::new(block_width_world, block_width_world);
-                let world_rect = WorldRect::new(world_pos, world_size);
-                let screen_box = ctx.camera.world_to_screen_rect(&world_rect);
+            let tex = &mut cached.tex;
+            let mut drawn = Ok(());
+            ctx.canvas.with_texture_canvas(tex, |tex_canvas| {
+                let color = tex_canvas.draw_color();
+                tex_canvas.set_draw_color(Color::RGBA(0, 0, 0, 0));
+                tex_canvas.clear();
+                tex_canvas.set_draw_color(color);
 
-                ctx.canvas.copy(
-                    tileset.tex,
-                    Some(tex_rect),
-                    Some(screen_rect_to_sdl(&screen_box)),
-                )?;
-            }
+                for y in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        let p = origin + TileVector::new(x as i32, y as i32);
+                        let kind = tiles.get(p);
+                        if kind.is_empty() {
+                            continue;
+                        }
+                        if sprite_map.is_tileset_animated(tileset_of(kind)) {
+                            cached.animated.push(p);
+                            continue;
+                        }
+
+                        let dst = FRect::new(
+                            (x as u32 * tile_pixels) as f32,
+                            (y as u32 * tile_pixels) as f32,
+                            tile_pixels as f32,
+                            tile_pixels as f32,
+                        );
+                        let tileset = sprite_map.get_tileset(tileset_of(kind));
+                        if let Err(err) = draw_tile(tex_canvas, &tileset, tiles, p, dst) {
+                            drawn = Err(err);
+                            return;
+                        }
+                    }
+                }
+            })?;
+            drawn?;
+            cached.revision = Some(revision);
+        }
+
+        ctx.canvas
+            .copy(&cached.tex, None, Some(screen_rect_to_sdl(&screen_rect)))?;
+
+        for p in &cached.animated {
+            let tileset = sprite_map.get_tileset_at(tileset_of(tiles.get(*p)), ctx.now_ms);
+            let world_rect = WorldRect::new(
+                tile_to_world(Point2D::new(p.x as f64, p.y as f64)),
+                WorldSize::splat(tile_world),
+            );
+            let screen_rect = ctx.camera.world_to_screen_rect(&world_rect);
+            draw_tile(
+                ctx.canvas,
+                &tileset,
+                tiles,
+                *p,
+                screen_rect_to_sdl(&screen_rect),
+            )?;
         }
     }
 
     Ok(())
 }
 
+/// Draws one tile into `dst`, with the pieces that connect it to its neighbors
+fn draw_tile<A: Allocator + Clone>(
+    canvas: &mut WindowCanvas,
+    tileset: &ResolvedTileset,
+    tiles: &ChunkedTileMap<TileKindId, A>,
+    p: TilePoint,
+    dst: FRect,
+) -> anyhow::Result<()> {
+    let variant = tileset.variant_at(p);
+    for (tex_rect, part) in tileset.tex_rects_for(tiles.same_kind_neighbors(p), variant) {
+        let part_rect = FRect::new(
+            dst.x + part.x * dst.w,
+            dst.y + part.y * dst.h,
+            part.w * dst.w,
+            part.h * dst.h,
+        );
+        canvas.copy(tileset.tex, Some(tex_rect), Some(part_rect))?;
+    }
+    Ok(())
+}
+
+/// Where a sprite map's cels are drawn for an entity, centered on its position like
+/// its collider is
+fn sprite_world_rect<A: Allocator>(sprite: &SpriteMap<'_, A>, pos: WorldPoint) -> WorldRect {
+    // TODO: proper pixel to world conversion somewhere
+    let bounds = sprite.bounds();
+    let size = WorldSize::new(bounds.w as f64, bounds.h as f64);
+    WorldRect::new(pos - size.to_vector() / 2.0, size)
+}
+
 pub fn update_and_render_animations<'gs, A: Allocator + Clone>(
     ctx: &mut Ctx<'gs, A>,
     prev: &Ecs<A>,
     next: &mut Ecs<A>,
 ) -> anyhow::Result<()> {
-    for (entity_id, prev_anims) in prev.sprite_anims_iter() {
-        let entity_id = *entity_id;
-
-        let pos = prev.pos_for_unchecked(entity_id);
+    for (entity_id, prev_anims, pos) in query!(prev, [sprite_anims, pos]) {
         let next_anims = next.sprite_anims_for_mut_unchecked(entity_id);
 
         for (prev_anim, next_anim) in prev_anims.iter().zip(next_anims) {
@@ -68,13 +322,16 @@ pub fn update_and_render_animations<'gs, A: Allocator + Clone>(
             // FIXME: u64 animation IDs
             let anim = sprite.get_animation(prev_anim.anim);
             let layer_cels = anim.update_cursor_loop(&mut next_anim.cursor, ctx.now_ms);
+            let sprite_rect = sprite_world_rect(&sprite, pos);
+            let bounds = sprite.bounds();
 
             for cel_i in layer_cels.iter() {
                 let cel = &sprite.cels[*cel_i as usize];
 
-                // TODO: proper pixel to world conversion somewhere
-                let world_pos =
-                    WorldPoint::new(pos.x + cel.src_rect.x as f64, pos.y + cel.src_rect.y as f64);
+                let world_pos = WorldPoint::new(
+                    sprite_rect.origin.x + (cel.src_rect.x - bounds.x) as f64,
+                    sprite_rect.origin.y + (cel.src_rect.y - bounds.y) as f64,
+                );
                 let world_size = WorldSize::new(cel.src_rect.w as f64, cel.src_rect.h as f64);
                 let world_rect = WorldRect::new(world_pos, world_size);
                 let screen_box = ctx.camera.world_to_screen_rect(&world_rect);
@@ -1,43 +1,465 @@
 //! Pathfinding, navigation etc
 
-use crate::{Ctx, ecs::Ecs};
+use allocator_api2::alloc::Allocator;
+use engine::{coords::TilePoint, resources::tile_kinds::TileKinds, tile_map::path::DiagonalMoves};
+
+use crate::{
+    Ctx, coords,
+    ecs::{Ecs, EntityId},
+    query,
+    spawnables::terrain::Terrain,
+};
+
+/// How followers move around the corners of walls
+const DIAGONALS: DiagonalMoves = DiagonalMoves::NoCornerCutting;
+
+/// The cost of walking onto a tile, or `None` if it's blocked or has no ground
+fn tile_cost<A: Allocator + Clone, KA: Allocator + Clone>(
+    terrain: &Terrain<A>,
+    kinds: &TileKinds<KA>,
+    p: TilePoint,
+) -> Option<f32> {
+    let ground = *terrain.ground.get(p);
+    if ground.is_empty() {
+        return None;
+    }
+
+    (!terrain.is_solid(kinds, p)).then(|| kinds.walk_cost(ground))
+}
+
+/// System to share one flow field between all the followers of a crowded target, or of
+/// any target once there are too many followers for each one to have a path
+pub mod flow {
+    use std::cmp::Reverse;
+
+    use allocator_api2::vec::Vec;
+    use engine::tile_map::flow_field::FlowField;
+
+    use super::*;
+    use crate::ecs::components::MAX_PATHS;
+
+    /// Targets with at least this many followers get a flow field instead of one path
+    /// per follower
+    const CROWD_SIZE: usize = 8;
+
+    /// How far from their target followers can be to use its flow field
+    const MAX_DISTANCE: f32 = 256.0;
+
+    /// The flow fields of crowded targets.
+    ///
+    /// They live outside of the ECS so they aren't copied between states every frame.
+    pub(crate) struct FlowFields<A: Allocator + Clone> {
+        allocator: A,
+        fields: Vec<(EntityId, FlowField<A>), A>,
+    }
+
+    impl<A: Allocator + Clone> FlowFields<A> {
+        pub fn new_in(allocator: A) -> Self {
+            Self {
+                fields: Vec::new_in(allocator.clone()),
+                allocator,
+            }
+        }
+
+        /// Get the flow field leading to a target, if it has enough followers
+        pub fn get(&self, target_entity: EntityId) -> Option<&FlowField<A>> {
+            self.fields
+                .iter()
+                .find(|(entity_id, _)| *entity_id == target_entity)
+                .map(|(_, field)| field)
+        }
+    }
+
+    pub fn update_and_render<'gs, A: Allocator + Clone>(
+        ctx: &mut Ctx<'gs, A>,
+        prev: &Ecs<A>,
+        next: &mut Ecs<A>,
+    ) -> anyhow::Result<()> {
+        let Some(res) = &ctx.resource_ids.terrain else {
+            return Ok(());
+        };
+        let Some(terrain) = ctx.terrain.as_ref() else {
+            return Ok(());
+        };
+        // only the layers that change the cost of tiles
+        let changes = || {
+            terrain
+                .ground
+                .changes()
+                .chain(terrain.walls.changes())
+                .chain(terrain.decoration.changes())
+        };
+        let has_changes = [&terrain.ground, &terrain.walls, &terrain.decoration]
+            .into_iter()
+            .any(|layer| layer.has_changes());
+        let kinds = ctx.resources.tile_kinds.get(res.kinds);
+        let fields = &mut *ctx.flow_fields;
+
+        // there are few targets, so a list is enough to count their followers
+        let mut followers = Vec::<(EntityId, usize), A>::new_in(ctx.allocator.clone());
+        for (_, follow) in prev.follow_iter() {
+            match followers
+                .iter_mut()
+                .find(|(target_id, _)| *target_id == follow.target_entity)
+            {
+                Some((_, count)) => *count += 1,
+                None => followers.push((follow.target_entity, 1)),
+            }
+        }
+
+        // the largest crowds come first, followed by the targets that get a field when
+        // there are too many followers for each one to have a path
+        followers.sort_unstable_by_key(|(_, count)| Reverse(*count));
+        let mut with_paths: usize = followers.iter().map(|(_, count)| count).sum();
+        let crowded = followers
+            .iter()
+            .take_while(|(_, count)| {
+                let crowded = *count >= CROWD_SIZE || with_paths > MAX_PATHS;
+                if crowded {
+                    with_paths -= count;
+                }
+                crowded
+            })
+            .count();
+        let followers = &followers[..crowded];
+
+        fields
+            .fields
+            .retain(|(target_id, _)| followers.iter().any(|(id, _)| id == target_id));
+
+        for &(target_id, _) in followers {
+            // despawned targets lose their followers in the follow system
+            let Some(target_pos) = prev.pos_for(target_id) else {
+                continue;
+            };
+
+            let cost = |p| tile_cost(terrain, &kinds, p);
+            let goal = coords::world_to_tile(target_pos);
+            match fields.fields.iter_mut().find(|(id, _)| *id == target_id) {
+                Some((_, field)) if field.goal() == Some(goal) => {
+                    if has_changes {
+                        field.update(changes(), cost);
+                    }
+                }
+                Some((_, field)) => field.build(goal, cost),
+                None => {
+                    let mut field =
+                        FlowField::new_in(fields.allocator.clone(), DIAGONALS, MAX_DISTANCE);
+                    field.build(goal, cost);
+                    fields.fields.push((target_id, field));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// System to find paths around the terrain for entities that follow another, again
+/// whenever the terrain changes. Followers stop following targets they can't reach
+pub mod path {
+    use allocator_api2::vec::Vec;
+    use engine::tile_map::path::Pathfinder;
+
+    use super::{flow::FlowFields, *};
+    use crate::ecs::components::{MAX_PATH_LEN, Path};
+
+    /// How many tiles to explore before deciding that a target can't be reached
+    const MAX_EXPLORED: usize = 4096;
+
+    pub fn update_and_render<'gs, A: Allocator + Clone>(
+        ctx: &mut Ctx<'gs, A>,
+        prev: &Ecs<A>,
+        next: &mut Ecs<A>,
+    ) -> anyhow::Result<()> {
+        let Some(res) = &ctx.resource_ids.terrain else {
+            return Ok(());
+        };
+        let Some(terrain) = ctx.terrain.as_ref() else {
+            return Ok(());
+        };
+        let kinds = ctx.resources.tile_kinds.get(res.kinds);
+        update_paths(
+            ctx.allocator.clone(),
+            &kinds,
+            terrain,
+            ctx.flow_fields,
+            prev,
+            next,
+        );
+
+        Ok(())
+    }
+
+    /// Find new paths for the followers whose path is outdated, and make the ones
+    /// whose target can't be reached stop following it
+    pub(super) fn update_paths<A: Allocator + Clone, KA: Allocator + Clone>(
+        allocator: A,
+        kinds: &TileKinds<KA>,
+        terrain: &Terrain<A>,
+        flow_fields: &FlowFields<A>,
+        prev: &Ecs<A>,
+        next: &mut Ecs<A>,
+    ) {
+        let revision = terrain.walk_revision();
+
+        // only allocate when something needs a new path
+        let mut pathfinder = None;
+        let mut tiles = Vec::new_in(allocator.clone());
+        // written once the outdated paths are dropped, and `None` is for the targets
+        // that can't be reached
+        let mut found = Vec::<(EntityId, Option<Path>), A>::new_in(allocator.clone());
+
+        for (follower_id, follow, pos) in query!(prev, [follow, pos]) {
+            // crowds are steered by their target's flow field instead
+            if flow_fields.get(follow.target_entity).is_some() {
+                continue;
+            }
+
+            let Some(target_pos) = prev.pos_for(follow.target_entity) else {
+                continue;
+            };
+            let start = coords::world_to_tile(pos);
+            let goal = coords::world_to_tile(target_pos);
+
+            if let Some(path) = prev.path_for(follower_id)
+                && path.goal == goal
+                && path.revision == revision
+                && !path.needs_extending()
+            {
+                continue;
+            }
+
+            let pathfinder = pathfinder.get_or_insert_with(|| {
+                Pathfinder::new_in(allocator.clone(), DIAGONALS, MAX_EXPLORED)
+            });
+            let reachable =
+                pathfinder.find_path(start, goal, |p| tile_cost(terrain, kinds, p), &mut tiles);
+
+            let path = reachable.then(|| {
+                let mut path = Path {
+                    goal,
+                    revision,
+                    ..Default::default()
+                };
+                path.tiles.extend(tiles.iter().copied().take(MAX_PATH_LEN));
+                path
+            });
+            found.push((follower_id, path));
+        }
+
+        // paths are only useful while following without a flow field. They're dropped
+        // before adding the new ones, as the flow system only leaves enough followers
+        // without a field for all of them to fit
+        for (entity_id, _, follow) in query!(prev, [path], optional[follow]) {
+            if follow.is_none_or(|follow| flow_fields.get(follow.target_entity).is_some()) {
+                next.unset_path_for(entity_id);
+            }
+        }
+
+        for (follower_id, path) in found {
+            match path {
+                Some(path) => next.overwrite_path_for(follower_id, path),
+                // give up instead of searching again every frame
+                None => {
+                    next.unset_follow_for(follower_id);
+                    if next.path_for(follower_id).is_some() {
+                        next.unset_path_for(follower_id);
+                    }
+                }
+            }
+        }
+    }
+}
 
 /// System to make an entity follow another
 pub mod follow {
-    use allocator_api2::alloc::Allocator;
+    use engine::coords::WorldPoint;
 
     use super::*;
 
     // TODO: follow speed as component?
     const SPEED_S: f64 = 500.0;
 
+    /// Move towards a point as far as the remaining travel distance allows.
+    /// Returns whether the point was reached
+    fn step_towards(pos: &mut WorldPoint, target: WorldPoint, travel: &mut f64) -> bool {
+        let diff = target - *pos;
+        let distance = diff.length();
+
+        if distance <= *travel {
+            *pos = target;
+            *travel -= distance;
+            true
+        } else {
+            *pos += diff.normalize() * *travel;
+            *travel = 0.0;
+            false
+        }
+    }
+
+    /// Moves followers along their path or their target's flow field if they have one,
+    /// which the navigation systems already updated this frame, and then straight to
+    /// their target
     pub fn update_and_render<'gs, A: Allocator + Clone>(
         ctx: &mut Ctx<'gs, A>,
         prev: &Ecs<A>,
         next: &mut Ecs<A>,
     ) -> anyhow::Result<()> {
-        for &(follower_id, follow) in prev.follow_iter() {
-            let follower_pos = prev.pos_for_unchecked(follower_id);
-            let target_pos = prev.pos_for_unchecked(follow.target_entity);
+        for (follower_id, follow, mut pos) in query!(prev, [follow, pos]) {
+            // the navigation systems gave up on reaching the target
+            if next.follow_for(follower_id).is_none() {
+                continue;
+            }
 
-            let diff = target_pos - follower_pos;
-            let distance = diff.length();
+            // the target was despawned
+            let Some(target_pos) = prev.pos_for(follow.target_entity) else {
+                next.unset_follow_for(follower_id);
+                continue;
+            };
 
-            let speed_per_frame = SPEED_S * ctx.delta_s;
+            let mut travel = SPEED_S * ctx.delta_s;
 
-            // likely to overshoot on the next frame if the distance is less than the travel
-            // distance per frame, so we just snap it to the target
-            let new_pos = if distance < speed_per_frame * 1.5 {
-                if follow.stop_after_arriving {
-                    next.unset_follow_for(follower_id);
+            if let Some(path) = next.path_for_mut(follower_id) {
+                while let Some(tile) = path.tiles.get(path.next) {
+                    if !step_towards(&mut pos, coords::tile_center_to_world(*tile), &mut travel) {
+                        break;
+                    }
+                    path.next += 1;
                 }
-                target_pos
-            } else {
-                follower_pos + diff.normalize() * speed_per_frame
-            };
 
-            next.set_pos_for(follower_id, new_pos);
+                // wait for the rest of the path instead of walking through walls
+                let walked = path.next >= path.tiles.len();
+                if !walked || path.needs_extending() {
+                    next.set_pos_for(follower_id, pos);
+                    continue;
+                }
+            } else if let Some(field) = ctx.flow_fields.get(follow.target_entity) {
+                // walk down the field until reaching the target's tile, or `None` if
+                // the field doesn't lead there from the follower's tile
+                let mut tile = coords::world_to_tile(pos);
+                let arrived = loop {
+                    match field.next_tile(tile) {
+                        Some(next_tile) if next_tile == tile => break Some(true),
+                        Some(next_tile) => {
+                            let center = coords::tile_center_to_world(next_tile);
+                            if !step_towards(&mut pos, center, &mut travel) {
+                                break Some(false);
+                            }
+                            tile = next_tile;
+                        }
+                        None => break None,
+                    }
+                };
+
+                match arrived {
+                    Some(true) => {}
+                    Some(false) => {
+                        next.set_pos_for(follower_id, pos);
+                        continue;
+                    }
+                    // give up like the path system does, instead of waiting forever
+                    None => {
+                        next.set_pos_for(follower_id, pos);
+                        next.unset_follow_for(follower_id);
+                        continue;
+                    }
+                }
+            }
+
+            // snapping to the target when it's within reach avoids overshooting it
+            if step_towards(&mut pos, target_pos, &mut travel) && follow.stop_after_arriving {
+                next.unset_follow_for(follower_id);
+            }
+
+            next.set_pos_for(follower_id, pos);
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use allocator_api2::alloc::Global;
+    use engine::resources::tile_kinds::TileKindId;
+
+    use super::{flow::FlowFields, path::update_paths, *};
+    use crate::ecs::{
+        EntitySpawner,
+        components::{Follow, Path},
+        tests::HeapEcs,
+    };
+
+    const KINDS: &str = r#"{
+        "type": "tile_kinds",
+        "kinds": [
+            { "id": 1, "name": "floor" },
+            { "id": 2, "name": "wall", "solid": true }
+        ]
+    }"#;
+
+    fn follow(target_entity: EntityId) -> Follow {
+        Follow {
+            stop_after_arriving: true,
+            target_entity,
+        }
+    }
+
+    #[test]
+    fn test_give_up_on_unreachable_target() {
+        let kinds = TileKinds::from_json_in(Global, KINDS).unwrap();
+        let mut terrain = Terrain::new_in(Global);
+        for x in 0..4 {
+            terrain.ground.set(TilePoint::new(x, 0), TileKindId(1));
+        }
+        terrain.walls.set(TilePoint::new(3, 0), TileKindId(2));
+        let revision = terrain.walk_revision();
+
+        let mut prev = HeapEcs::new();
+        let in_wall = EntitySpawner::new()
+            .with_pos(coords::tile_center_to_world(TilePoint::new(3, 0)))
+            .spawn(&mut prev);
+        let on_floor = EntitySpawner::new()
+            .with_pos(coords::tile_center_to_world(TilePoint::new(2, 0)))
+            .spawn(&mut prev);
+
+        // a follower with an up to date path, which is kept as it is
+        let mut path = Path {
+            goal: TilePoint::new(2, 0),
+            revision,
+            ..Default::default()
+        };
+        path.tiles
+            .extend([TilePoint::new(1, 0), TilePoint::new(2, 0)]);
+        let walking = EntitySpawner::new()
+            .with_pos(coords::tile_center_to_world(TilePoint::new(0, 0)))
+            .with_follow(follow(on_floor))
+            .with_path(path)
+            .spawn(&mut prev);
+
+        // a follower that never had a path, whose target is in a wall
+        let stuck = EntitySpawner::new()
+            .with_pos(coords::tile_center_to_world(TilePoint::new(0, 0)))
+            .with_follow(follow(in_wall))
+            .spawn(&mut prev);
+
+        let mut next = HeapEcs::new();
+        next.clone_from(&prev);
+        update_paths(
+            Global,
+            &kinds,
+            &terrain,
+            &FlowFields::new_in(Global),
+            &prev,
+            &mut next,
+        );
+
+        assert!(next.follow_for(stuck).is_none());
+        assert!(next.path_for(stuck).is_none());
+        assert!(next.follow_for(walking).is_some());
+        assert_eq!(
+            next.path_for(walking).unwrap().tiles.as_slice(),
+            &[TilePoint::new(1, 0), TilePoint::new(2, 0)]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! Collisions between entities and the terrain
+
+use allocator_api2::alloc::Allocator;
+use engine::tile_map::collision;
+use euclid::Box2D;
+
+use crate::{
+    Ctx,
+    coords::{self, WORLD_TO_TILE},
+    ecs::{Ecs, components::Collider},
+    query,
+};
+
+/// System to stop entities with a collider from moving through solid tiles.
+///
+/// It runs after everything that moves entities, and replays each entity's move of
+/// this frame from where it was so it slides along the walls in its way.
+pub fn update_and_render<'gs, A: Allocator + Clone>(
+    ctx: &mut Ctx<'gs, A>,
+    prev: &Ecs<A>,
+    next: &mut Ecs<A>,
+) -> anyhow::Result<()> {
+    let Some(res) = &ctx.resource_ids.terrain else {
+        return Ok(());
+    };
+    let Some(terrain) = ctx.terrain.as_ref() else {
+        return Ok(());
+    };
+    let kinds = ctx.resources.tile_kinds.get(res.kinds);
+
+    for (entity_id, collider, from) in query!(prev, [collider, pos]) {
+        let Some(to) = next.pos_for(entity_id) else {
+            continue;
+        };
+
+        let center = coords::world_to_fractional_tile(from);
+        let half = collider.size.to_vector().cast_unit() * WORLD_TO_TILE / 2.0;
+        let bounds = Box2D::new(center - half, center + half);
+        let motion = ((to - from) * WORLD_TO_TILE).cast_unit();
+
+        let moved = collision::move_box(bounds, motion, |p| terrain.is_solid(&kinds, p));
+
+        next.set_pos_for(entity_id, from + moved.motion.cast_unit() / WORLD_TO_TILE);
+        next.set_collider_for(
+            entity_id,
+            Collider {
+                contacts: moved.contacts,
+                ..collider
+            },
+        );
+    }
+
+    Ok(())
+}
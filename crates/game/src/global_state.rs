@@ -3,7 +3,13 @@ use derivative::Derivative;
 use engine::{camera::Camera, resources::Resources};
 use sdl3::render::WindowCanvas;
 
-use crate::{ecs::Ecs, spawnables};
+use crate::{
+    ecs::{
+        Ecs, EntityId,
+        systems::{draw::cache::TerrainCache, navigation::flow::FlowFields},
+    },
+    spawnables::{self, terrain::Terrain},
+};
 
 /// The map of known resource IDs
 pub(crate) struct ResourceIds {
@@ -17,21 +23,25 @@ pub(crate) struct ResourceIds {
 pub(crate) struct State<A: Allocator + Clone> {
     // World objects
     pub ecs: Ecs<A>,
-    pub zorb: usize,
-    pub terrain: usize,
+    pub zorb: EntityId,
+    /// Where followers go to
+    pub target: EntityId,
 }
 
 /// The global memory block that is used by the game
 pub(crate) struct MemoryPool<A: Allocator + Clone> {
     // Object and resource management
     pub resource_ids: ResourceIds,
+    pub flow_fields: FlowFields<A>,
+    pub terrain_cache: TerrainCache<A>,
+    /// `None` until the first frame loads it
+    pub terrain: Option<Terrain<A>>,
 
     pub prev: State<A>,
     pub next: State<A>,
 }
 
 /// A context object that can be passed around throughout the game
-#[expect(dead_code)]
 pub(crate) struct Ctx<'gs, A: Allocator + Clone> {
     pub allocator: A,
     pub canvas: &'gs mut WindowCanvas,
@@ -39,6 +49,9 @@ pub(crate) struct Ctx<'gs, A: Allocator + Clone> {
 
     pub resources: &'gs mut Resources<'gs, A>,
     pub resource_ids: &'gs mut ResourceIds,
+    pub flow_fields: &'gs mut FlowFields<A>,
+    pub terrain_cache: &'gs mut TerrainCache<A>,
+    pub terrain: &'gs mut Option<Terrain<A>>,
 
     pub now_ms: u64,
     pub delta_ms: u64,
@@ -10,19 +10,32 @@ mod ecs;
 mod global_state;
 mod spawnables;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
 
 use allocator_api2::alloc::{Allocator, Global as GlobalAllocator, Layout};
 use anyhow::Result;
-use ecs::components::{Follow, SpriteAnim, SpriteAnims};
-use ecs::{EntitySpawner, SENTINEL};
-use engine::coords::WorldPoint;
+use ecs::components::Follow;
+use ecs::systems::draw::cache::TerrainCache;
+use ecs::systems::navigation::flow::FlowFields;
+use ecs::{EntitySpawner, NULL_ENTITY};
+use engine::coords::{ScreenPoint, ScreenSize, WorldPoint};
+use engine::events::Stick;
 use engine::hooks::{DropParams, InitParams, UpdateAndRenderParams};
 use engine::types::Reset;
+use sdl3::gamepad::{Axis, Button};
 
 use global_state::{Ctx, MemoryPool};
 
+/// Where resources are loaded from, relative to the working directory
+const RESOURCE_ROOT: &str = "resources/obj";
+
+/// Where the painted terrain is saved, relative to `RESOURCE_ROOT`.
+///
+/// Each path gets its own form of the same level: binary and JSON. The binary one is
+/// loaded instead of the start map's terrain on the next run.
+const PAINTED_LEVEL_PATHS: [&str; 2] = ["levels/painted.lvl", "levels/painted.lvl.json"];
+
 #[unsafe(no_mangle)]
 extern "Rust" fn init<'gs>(
     params: &'gs mut InitParams<'gs, 'gs, GlobalAllocator>,
@@ -32,7 +45,7 @@ extern "Rust" fn init<'gs>(
 
     let pool = unsafe { ptr.cast::<MemoryPool<GlobalAllocator>>().as_mut() };
 
-    params.resources.set_root(PathBuf::from("resources/obj"));
+    params.resources.set_root(PathBuf::from(RESOURCE_ROOT));
 
     params
         .camera
@@ -43,6 +56,15 @@ extern "Rust" fn init<'gs>(
     // with zeros
     pool.prev.ecs.reset();
     pool.next.ecs.reset();
+    // SAFETY: the zeroed memory isn't a valid value, so it must not be dropped
+    unsafe {
+        std::ptr::write(&mut pool.flow_fields, FlowFields::new_in(params.allocator));
+        std::ptr::write(
+            &mut pool.terrain_cache,
+            TerrainCache::new_in(params.allocator),
+        );
+        std::ptr::write(&mut pool.terrain, None);
+    }
 
     pool.resource_ids.terrain = Some(spawnables::terrain::load_resources(params.resources)?);
     pool.resource_ids.zorb = Some(spawnables::zorb::load_resources(params.resources)?);
@@ -55,6 +77,14 @@ extern "Rust" fn drop(params: DropParams<GlobalAllocator>) {
     // TODO: unload resources
     let layout = Layout::new::<MemoryPool<GlobalAllocator>>();
     unsafe {
+        let pool = params.memory.cast::<MemoryPool<GlobalAllocator>>().as_mut();
+        // the flow fields own memory from the allocator
+        std::ptr::drop_in_place(&mut pool.flow_fields);
+        // destroys the cached textures, which must happen while the renderer is alive
+        std::ptr::drop_in_place(&mut pool.terrain_cache);
+        // the terrain's layers own memory from the allocator too
+        std::ptr::drop_in_place(&mut pool.terrain);
+
         params
             .allocator
             .deallocate(params.memory.cast::<u8>(), layout);
@@ -76,34 +106,61 @@ extern "Rust" fn update_and_render<'gs>(
         now_ms: params.now_ms,
         resources: params.resources,
         resource_ids: &mut pool.resource_ids,
+        flow_fields: &mut pool.flow_fields,
+        terrain_cache: &mut pool.terrain_cache,
+        terrain: &mut pool.terrain,
         screen_w: params.screen_w,
         screen_h: params.screen_h,
     };
 
-    if params.events.quit() || params.events.key(sdl3::keyboard::Scancode::Escape).down {
+    if params.events.quit()
+        || params.events.key(sdl3::keyboard::Scancode::Escape).down
+        || params.events.gamepad_btn(0, Button::Back).down
+    {
         return Ok(false);
     }
 
     // generate tile map
-    if pool.prev.terrain == SENTINEL {
-        pool.next.terrain = spawnables::terrain::spawn(&mut ctx, &mut pool.next.ecs);
+    if let Some(terrain) = ctx.terrain.as_mut() {
+        // the systems follow the tiles changed during this frame
+        terrain.clear_changes();
+    } else {
+        spawnables::terrain::spawn(&mut ctx, &mut pool.next.ecs)?;
     }
 
-    // let right_mouse = params.events.mouse_btn(sdl3::mouse::MouseButton::Right);
-    // if right_mouse.down && pool.prev.zorb == SENTINEL {
-    //     pool.next.zorb = spawnables::zorb::spawn(&mut ctx, &mut pool.next.ecs);
-    // }
+    // send everything that's animated to where the right mouse clicks
+    let right_mouse = params.events.mouse_btn(sdl3::mouse::MouseButton::Right);
+    if right_mouse.down && right_mouse.since == ctx.now_ms {
+        let target_pos = ctx.camera.screen_to_world_point(&right_mouse.pos);
+        if pool.prev.target == NULL_ENTITY {
+            pool.next.target = EntitySpawner::new()
+                .with_pos(target_pos)
+                .spawn(&mut pool.next.ecs);
+        } else {
+            pool.next.ecs.set_pos_for(pool.prev.target, target_pos);
+        }
+
+        for (entity_id, _) in pool.prev.ecs.sprite_anims_iter() {
+            pool.next.ecs.overwrite_follow_for(
+                *entity_id,
+                Follow {
+                    stop_after_arriving: true,
+                    target_entity: pool.next.target,
+                },
+            );
+        }
+    }
 
     let left_mouse = params.events.mouse_btn(sdl3::mouse::MouseButton::Left);
     if left_mouse.down {
         let world_pos = ctx.camera.screen_to_world_point(&left_mouse.pos);
         let tile_pos = coords::world_to_tile(world_pos);
 
-        let terrain = pool.next.ecs.terrain_for_mut_unchecked(pool.prev.terrain);
-        let tile = terrain
-            .tiles
-            .get_mut(tile_pos.x as usize, tile_pos.y as usize);
-        tile.0 = true;
+        let wall = ctx.resource_ids.terrain.as_ref().unwrap().wall;
+        let terrain = ctx.terrain.as_mut().expect("Terrain should be spawned");
+        if *terrain.walls.get(tile_pos) != wall {
+            terrain.walls.set(tile_pos, wall);
+        }
 
         // TODO: move this to an input handling system
         // let follow_pos = ctx.camera.screen_to_world_point(&left_mouse.pos);
@@ -136,6 +193,22 @@ extern "Rust" fn update_and_render<'gs>(
         // TODO: notify entity to delete itself
     }
 
+    let save_key = params.events.key(sdl3::keyboard::Scancode::F5);
+    if save_key.down
+        && save_key.since == ctx.now_ms
+        && let Some(terrain) = ctx.terrain.as_ref()
+    {
+        for path in PAINTED_LEVEL_PATHS {
+            let full_path = Path::new(RESOURCE_ROOT).join(path);
+            if let Err(err) = spawnables::terrain::save(terrain, &full_path) {
+                sdl3::log::log_warn(
+                    sdl3::log::Category::Application,
+                    &format!("Failed to save level to {}: {err}", full_path.display()),
+                );
+            }
+        }
+    }
+
     if params.events.key(sdl3::keyboard::Scancode::W).down {
         ctx.camera.pos.y -= 30.0 * ctx.delta_ms as f64 / 1000.0;
     }
@@ -157,6 +230,33 @@ extern "Rust" fn update_and_render<'gs>(
             .change_zoom_around(-(ctx.delta_ms as f64) / 1000.0, params.events.mouse_pos);
     }
 
+    let stick = params.events.gamepad_stick(0, Stick::Left);
+    ctx.camera.pos.x += stick.x * 30.0 * ctx.delta_s;
+    ctx.camera.pos.y += stick.y * 30.0 * ctx.delta_s;
+
+    let zoom_in = params.events.gamepad_axis(0, Axis::TriggerRight)
+        - params.events.gamepad_axis(0, Axis::TriggerLeft);
+    if zoom_in != 0.0 {
+        let screen_center = ScreenPoint::new(ctx.screen_w as f64 / 2.0, ctx.screen_h as f64 / 2.0);
+        ctx.camera
+            .change_zoom_around(zoom_in * ctx.delta_s, screen_center);
+    }
+
+    if params.events.wheel.y != 0.0 {
+        ctx.camera
+            .change_zoom_around(params.events.wheel.y * 0.1, params.events.mouse_pos);
+    }
+    if let Some(drag) = params.events.drag()
+        && drag.btn == sdl3::mouse::MouseButton::Middle
+    {
+        let delta = params.events.mouse_delta;
+        let world_delta = ctx
+            .camera
+            .screen_to_world_size(&ScreenSize::new(delta.x, delta.y));
+        ctx.camera.pos.x -= world_delta.width;
+        ctx.camera.pos.y -= world_delta.height;
+    }
+
     pool.next.ecs.update_and_render(&mut ctx, &pool.prev.ecs)?;
     pool.prev.clone_from(&pool.next);
 
@@ -1,2 +1,21 @@
+use allocator_api2::alloc::Allocator;
+use engine::resources::tiled::TiledObject;
+
+use crate::{
+    ecs::{Ecs, EntityId},
+    global_state::ResourceIds,
+};
+
 pub mod terrain;
 pub mod zorb;
+
+/// Spawns the entity for an object placed in a map and returns it
+pub type ObjectSpawner<A> = fn(&ResourceIds, &mut Ecs<A>, &TiledObject) -> EntityId;
+
+/// Get the spawner for a class of map objects
+pub fn object_spawner<A: Allocator + Clone>(class: &str) -> Option<ObjectSpawner<A>> {
+    match class {
+        "zorb" => Some(zorb::spawn_object),
+        _ => None,
+    }
+}
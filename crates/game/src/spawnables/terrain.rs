@@ -1,58 +1,233 @@
+use std::path::Path;
+
 use allocator_api2::alloc::Allocator;
 use engine::{
+    coords::TilePoint,
     resources::{
         Resources,
+        level::{Level, LevelIdMarker},
         manager::ResourceError,
         sprite_map::{SpriteMapIdMarker, Tileset},
+        tile_kinds::{MAX_TILE_KINDS, TileKindId, TileKinds, TileKindsIdMarker},
+        tiled::TiledMapIdMarker,
     },
+    tile_map::chunked::ChunkedTileMap,
     types::Id,
 };
 
-use crate::{
-    Ctx,
-    ecs::{
-        Ecs, EntitySpawner,
-        components::{Terrain, Tile},
-    },
-};
+use crate::{Ctx, ecs::Ecs, spawnables};
+
+/// The tile layers of the world, from bottom to top.
+///
+/// Ground, walls and decoration are drawn below entities and the overlay above them.
+/// It's kept out of the double-buffered `Ecs`, as copying every layer between states on
+/// every frame would be too slow, so systems see the tiles painted during the frame
+/// they run in.
+pub(crate) struct Terrain<A: Allocator + Clone> {
+    allocator: A,
+
+    pub ground: ChunkedTileMap<TileKindId, A>,
+    pub walls: ChunkedTileMap<TileKindId, A>,
+    pub decoration: ChunkedTileMap<TileKindId, A>,
+    pub overlay: ChunkedTileMap<TileKindId, A>,
+}
+
+impl<A: Allocator + Clone> Terrain<A> {
+    pub fn new_in(allocator: A) -> Self {
+        Self {
+            ground: ChunkedTileMap::new_in(allocator.clone()),
+            walls: ChunkedTileMap::new_in(allocator.clone()),
+            decoration: ChunkedTileMap::new_in(allocator.clone()),
+            overlay: ChunkedTileMap::new_in(allocator.clone()),
+            allocator,
+        }
+    }
+
+    /// Every layer, from bottom to top
+    pub fn layers(&self) -> [&ChunkedTileMap<TileKindId, A>; 4] {
+        [&self.ground, &self.walls, &self.decoration, &self.overlay]
+    }
+
+    fn layers_mut(&mut self) -> [&mut ChunkedTileMap<TileKindId, A>; 4] {
+        [
+            &mut self.ground,
+            &mut self.walls,
+            &mut self.decoration,
+            &mut self.overlay,
+        ]
+    }
+
+    /// Whether a tile blocks entities, which is when any layer they can bump into has
+    /// a solid kind
+    pub fn is_solid<KA: Allocator + Clone>(&self, kinds: &TileKinds<KA>, p: TilePoint) -> bool {
+        [self.walls.get(p), self.decoration.get(p)]
+            .into_iter()
+            .any(|kind| kinds.is_solid(*kind))
+    }
+
+    /// A number that changes whenever the layers that decide where entities can walk
+    /// may have been written to
+    pub fn walk_revision(&self) -> u64 {
+        [&self.ground, &self.walls, &self.decoration]
+            .into_iter()
+            .fold(0u64, |acc, layer| acc.wrapping_add(layer.revision()))
+    }
+
+    /// Forget which tiles changed in every layer, so only the changes of the current
+    /// frame are left to follow
+    pub fn clear_changes(&mut self) {
+        for layer in self.layers_mut() {
+            layer.clear_changes();
+        }
+    }
+}
 
 pub struct ResourceIds {
     pub sprite: Id<SpriteMapIdMarker>,
-    pub tileset: Id<Tileset>,
+    pub kinds: Id<TileKindsIdMarker>,
+    pub map: Id<TiledMapIdMarker>,
+    /// The terrain painted and saved during an earlier run, which replaces the one of
+    /// the map
+    pub painted: Option<Id<LevelIdMarker>>,
+
+    pub wall: TileKindId,
+
+    /// The tileset of each layer, used for kinds that don't have their own
+    pub ground: Id<Tileset>,
+    pub walls: Id<Tileset>,
+    pub decoration: Id<Tileset>,
+    pub overlay: Id<Tileset>,
+
+    /// The tileset of each kind that has its own, indexed by kind ID
+    pub kind_tilesets: [Option<Id<Tileset>>; MAX_TILE_KINDS],
 }
 
+/// Used by layers that don't have their own tileset in the sprite map yet
+const FALLBACK_TILESET: &str = "mask";
+
 pub fn load_resources<'r, A: Allocator + Clone>(
     res: &'r Resources<'r, A>,
 ) -> Result<ResourceIds, ResourceError> {
-    res.sprites
-        .load("tiles/mask")?
-        .and_then(|sprite_id, sprite| {
+    let sprite = res.sprites.load("tiles/mask")?;
+    let kinds = res.tile_kinds.load("tiles/kinds")?;
+    let map = res.tiled_maps.load("levels/start")?.and_then(|id, _| id);
+    // nothing was saved yet when there is no file to load
+    let painted = match res.levels.load(crate::PAINTED_LEVEL_PATHS[0]) {
+        Ok(level) => Some(level.and_then(|id, _| id)),
+        Err(ResourceError::LoadFailed) => None,
+        Err(err) => return Err(err),
+    };
+
+    kinds.and_then(|kinds_id, kinds| {
+        sprite.and_then(|sprite_id, sprite| {
+            let tileset = |name| {
+                sprite
+                    .find_tileset_id(name)
+                    .unwrap_or_else(|| sprite.get_tileset_id(FALLBACK_TILESET))
+            };
+
+            let mut kind_tilesets = [None; MAX_TILE_KINDS];
+            for kind in kinds.iter() {
+                kind_tilesets[kind.id.0 as usize] = kind
+                    .tileset
+                    .as_deref()
+                    .and_then(|t| sprite.find_tileset_id(t));
+            }
+
             Ok(ResourceIds {
                 sprite: sprite_id,
-                tileset: sprite.get_tileset_id("mask"),
+                kinds: kinds_id,
+                map,
+                painted,
+                wall: kinds.get_id("wall"),
+                ground: tileset("ground"),
+                walls: tileset("walls"),
+                decoration: tileset("decoration"),
+                overlay: tileset("overlay"),
+                kind_tilesets,
             })
         })
+    })
 }
 
-fn generate() -> Terrain {
-    let mut terrain = Terrain::default();
-    for v in 1..51 {
-        terrain.tiles.set(v, 1, Tile(true));
-        terrain.tiles.set(v, 50, Tile(true));
-        terrain.tiles.set(1, v, Tile(true));
-        terrain.tiles.set(50, v, Tile(true));
-        terrain.tiles.set(25, v, Tile(true));
-        terrain.tiles.set(v, 25, Tile(true));
+/// The name of each terrain layer in level files, from bottom to top
+const LAYER_NAMES: [&str; 4] = ["ground", "walls", "decoration", "overlay"];
+
+/// Fails if the level has tiles of kinds that aren't in the registry
+fn from_level<A: Allocator + Clone, LA: Allocator + Clone, KA: Allocator + Clone>(
+    allocator: A,
+    level: &Level<LA>,
+    kinds: &TileKinds<KA>,
+) -> Result<Terrain<A>, ResourceError> {
+    level.check_kinds(kinds)?;
+
+    let mut terrain = Terrain::new_in(allocator);
+    for (name, tiles) in LAYER_NAMES.into_iter().zip(terrain.layers_mut()) {
+        let Some(level_tiles) = level.layer(name) else {
+            continue;
+        };
+        for (p, kind) in level_tiles.iter().filter(|(_, kind)| !kind.is_empty()) {
+            tiles.set(p, *kind);
+        }
     }
 
-    terrain
+    Ok(terrain)
 }
 
-pub fn spawn<'gs, A: Allocator + Clone>(_ctx: &mut Ctx<'gs, A>, ecs: &mut Ecs<A>) -> usize {
-    let terrain = generate();
+/// Save the terrain as a level, in JSON form if the path ends in `.json`
+pub fn save<A: Allocator + Clone>(terrain: &Terrain<A>, path: &Path) -> Result<(), ResourceError> {
+    let mut level = Level::new_in(terrain.allocator.clone());
+    for (name, tiles) in LAYER_NAMES.into_iter().zip(terrain.layers()) {
+        level.push_layer(name, tiles.clone());
+    }
+    level.save(path)
+}
+
+/// Load the terrain of the start map, or the painted one if it was saved, and spawn the
+/// objects placed in the map
+pub fn spawn<'gs, A: Allocator + Clone>(
+    ctx: &mut Ctx<'gs, A>,
+    ecs: &mut Ecs<A>,
+) -> anyhow::Result<()> {
+    let res = ctx
+        .resource_ids
+        .terrain
+        .as_ref()
+        .expect("Terrain resources should be loaded");
+    let map = ctx.resources.tiled_maps.get(res.map);
+    let kinds = ctx.resources.tile_kinds.get(res.kinds);
+
+    let terrain = match res.painted {
+        Some(painted) => from_level(
+            ctx.allocator.clone(),
+            &ctx.resources.levels.get(painted),
+            &kinds,
+        )?,
+        None => {
+            let level = map.to_level_in(ctx.allocator.clone(), &kinds)?;
+            from_level(ctx.allocator.clone(), &level, &kinds)?
+        }
+    };
+
+    // the cached chunks were drawn from another terrain
+    ctx.terrain_cache.clear();
+
+    *ctx.terrain = Some(terrain);
+
+    for object in map.objects.iter() {
+        match spawnables::object_spawner(&object.class) {
+            Some(spawner) => {
+                spawner(ctx.resource_ids, ecs, object);
+            }
+            None => sdl3::log::log_warn(
+                sdl3::log::Category::Application,
+                &format!(
+                    "No spawner for object {} of class '{}'",
+                    object.id, object.class
+                ),
+            ),
+        }
+    }
 
-    EntitySpawner::new()
-        .with_pos_default()
-        .with_terrain(terrain)
-        .spawn(ecs)
+    Ok(())
 }
@@ -1,52 +1,67 @@
 use allocator_api2::alloc::Allocator;
 use engine::{
-    coords::WorldPoint,
+    coords::WorldSize,
     resources::{
         Resources,
         manager::ResourceError,
         sprite_map::{SpriteMapAnimation, SpriteMapIdMarker},
+        tiled::TiledObject,
     },
     types::Id,
 };
 
 use crate::{
-    Ctx,
+    coords,
     ecs::{
-        Ecs, EntitySpawner,
-        components::{SpriteAnim, SpriteAnims},
+        Ecs, EntityId, EntitySpawner,
+        components::{Collider, SpriteAnim, SpriteAnims},
     },
+    global_state,
 };
 
 pub struct ResourceIds {
     pub sprite: Id<SpriteMapIdMarker>,
     pub anim_body_idle: Id<SpriteMapAnimation>,
+    #[expect(dead_code)]
     pub anim_body_walk: Id<SpriteMapAnimation>,
     pub anim_face_cute: Id<SpriteMapAnimation>,
+    /// The size of the drawn sprite, so walls stop a zorb where it looks like they do
+    pub collider_size: WorldSize,
 }
 
 pub fn load_resources<'r, A: Allocator + Clone>(
     res: &'r Resources<'r, A>,
 ) -> Result<ResourceIds, ResourceError> {
     res.sprites.load("zorb")?.and_then(|sprite_id, sprite| {
+        // TODO: proper pixel to world conversion somewhere
+        let bounds = sprite.bounds();
+
         Ok(ResourceIds {
             sprite: sprite_id,
             anim_body_idle: sprite.get_animation_id("body:idle"),
             anim_body_walk: sprite.get_animation_id("body:walk"),
             anim_face_cute: sprite.get_animation_id("face:cute"),
+            collider_size: WorldSize::new(bounds.w as f64, bounds.h as f64),
         })
     })
 }
 
-pub fn spawn<'gs, A: Allocator + Clone>(ctx: &mut Ctx<'gs, A>, ecs: &mut Ecs<A>) -> usize {
-    let res = ctx.resource_ids.zorb.as_ref().unwrap();
+/// Spawn a zorb where a map object was placed
+pub fn spawn_object<A: Allocator + Clone>(
+    ids: &global_state::ResourceIds,
+    ecs: &mut Ecs<A>,
+    object: &TiledObject,
+) -> EntityId {
+    let res = ids.zorb.as_ref().unwrap();
     let anims = SpriteAnims::from_array([
         SpriteAnim::from_sprite(res.sprite, res.anim_body_idle),
         SpriteAnim::from_sprite(res.sprite, res.anim_face_cute),
     ]);
 
     let mut spawner = EntitySpawner::new()
-        .with_pos(WorldPoint::new(400.0, 400.0))
-        .with_sprite_anims(anims);
+        .with_pos(coords::tile_to_world(object.rect.center()))
+        .with_sprite_anims(anims)
+        .with_collider(Collider::new(res.collider_size));
 
     #[cfg(debug_assertions)]
     {
@@ -319,7 +319,7 @@ pub fn gen_code() {
 
     let header = "// DO NOT EDIT: This code was autogenerated.
 
-pub(crate) const MASKS: [(u8, u8); 256] = [";
+pub(crate) const BLOB_MASKS: [(u8, u8); 256] = [";
 
     writeln!(file, "{header}").unwrap();
 